@@ -0,0 +1,200 @@
+use crate::api::WorkflowResult;
+use crate::config::APIConfig;
+use crate::context::TranslationContext;
+use crate::line_ending::LineEndings;
+use crate::pipeline::{extract_translation, process_task, Job};
+use crate::state::Usage;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::future::Future;
+use std::pin::Pin;
+
+pub const DEFAULT_ALIGN_RETRIES: usize = 1;
+
+// 行数不一致时的修复方式, 记录在任务状态中
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Repair {
+    Retried,
+    Split,
+    PerLine,
+    PerUnit,
+}
+
+impl Repair {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Repair::Retried => "重新请求",
+            Repair::Split => "切分",
+            Repair::PerLine => "逐行",
+            Repair::PerUnit => "逐段",
+        }
+    }
+}
+
+// preserve_layout 为 true 时只发送去掉缩进的正文, 缩进和只有空白的行原样写入译文
+#[derive(Clone, Copy)]
+pub struct AlignOptions {
+    pub retries: usize,
+    pub preserve_layout: bool,
+    pub line_endings: LineEndings,
+}
+
+pub struct Aligner<'a> {
+    pub job: &'a Job,
+    pub api_config: &'a APIConfig,
+    pub output_key: &'a str,
+    pub options: AlignOptions,
+}
+
+pub type SplitFuture<'b> = Pin<Box<dyn Future<Output = Result<(Vec<String>, bool), String>> + Send + 'b>>;
+
+// 对半切分时每一部分的请求方式, 按行对齐的纯文本和结构化文件的批次共用切分逻辑
+// request_part 在译文数量与发送的一致时返回译文, 否则返回 None 继续切分
+pub trait SplitRequest: Sync {
+    type Item: ?Sized + Sync;
+
+    fn request_part(&self, part: &[&Self::Item], usage: &mut Usage) -> impl Future<Output = Result<Option<Vec<String>>, String>> + Send;
+    fn request_single(&self, item: &Self::Item, usage: &mut Usage) -> impl Future<Output = Result<String, String>> + Send;
+}
+
+// 对半切分递归翻译, 只剩一项时单独翻译, 返回的 bool 表示是否用到了单独翻译
+pub fn translate_split<'b, R: SplitRequest>(
+    requester: &'b R,
+    items: &'b [&'b R::Item],
+    usage: &'b mut Usage
+) -> SplitFuture<'b> {
+    Box::pin(async move {
+        if items.len() == 1 {
+            return Ok((vec![requester.request_single(items[0], usage).await?], true));
+        }
+
+        let (head, tail) = items.split_at(items.len() / 2);
+        let mut translated = Vec::new();
+        let mut single = false;
+        for part in [head, tail] {
+            match requester.request_part(part, usage).await? {
+                Some(lines) => translated.extend(lines),
+                None => {
+                    let (lines, part_single) = translate_split(requester, part, usage).await?;
+                    translated.extend(lines);
+                    single |= part_single;
+                }
+            }
+        }
+        Ok((translated, single))
+    })
+}
+
+impl Aligner<'_> {
+    // lines 是该块覆盖的所有源文本行, 包括空行和每行末尾的换行符
+    // 译文的非空行数与原文不一致时重新请求, 仍不一致则对半切分递归翻译, 最后逐行翻译
+    // 返回的译文与原文逐行对应, 原文的空行在译文中保留为空行, 每行按原文的换行符结束
+    pub async fn translate(
+        &self,
+        lines: &[String],
+        context: Option<&TranslationContext>
+    ) -> Result<(WorkflowResult, Option<Repair>), String> {
+        let sent: Vec<&str> = lines.iter().map(|line| self.content(line)).collect();
+        let content: Vec<&str> = sent.iter().copied().filter(|line| !line.is_empty()).collect();
+        let mut usage = Usage::default();
+
+        let mut repair = None;
+        let mut translated = None;
+        if !content.is_empty() {
+            let text = trim_blank_lines(&sent);
+            for attempt in 0..=self.options.retries {
+                let result = self.request(&text, context, &mut usage).await?;
+                if non_empty_lines(&result).len() == content.len() {
+                    repair = (attempt > 0).then_some(Repair::Retried);
+                    translated = Some(non_empty_lines(&result));
+                    break;
+                }
+            }
+        }
+
+        let translated = match translated {
+            Some(translated) => translated,
+            None if content.is_empty() => Vec::new(),
+            None => {
+                let (translated, per_line) = translate_split(&LineRequest { aligner: self, context }, &content, &mut usage).await?;
+                repair = Some(if per_line { Repair::PerLine } else { Repair::Split });
+                translated
+            }
+        };
+
+        let line_endings = self.options.line_endings;
+        let mut translated = translated.into_iter();
+        let output: String = lines.iter()
+            .map(|line| {
+                let body = match (line.trim().is_empty(), self.options.preserve_layout) {
+                    (true, true) => line.trim_end_matches(['\n', '\r']).to_string(),
+                    (true, false) => String::new(),
+                    (false, true) => {
+                        let indent = &line[..line.len() - line.trim_start().len()];
+                        format!("{}{}", indent, translated.next().unwrap_or_default().trim_start())
+                    }
+                    (false, false) => translated.next().unwrap_or_default(),
+                };
+                let trailing = if line.trim().is_empty() { "" } else { line_endings.trailing_whitespace(line) };
+                format!("{}{}{}", body, trailing, line_endings.line_terminator(line))
+            })
+            .collect();
+
+        let result = WorkflowResult {
+            outputs: json!({ self.output_key: output }),
+            total_tokens: usage.total_tokens,
+            elapsed_time: usage.elapsed_time,
+        };
+        Ok((result, repair))
+    }
+
+    // 发送给工作流的一行, 只有空白的行为空
+    fn content<'l>(&self, line: &'l str) -> &'l str {
+        if self.options.preserve_layout {
+            line.trim()
+        } else if line.trim().is_empty() {
+            ""
+        } else {
+            line.trim_end()
+        }
+    }
+
+    async fn request(&self, text: &str, context: Option<&TranslationContext>, usage: &mut Usage) -> Result<String, String> {
+        let result = process_task(&self.job.config_data, self.api_config, &self.job.term, text.to_string(), context).await;
+        let (translation, result) = extract_translation(result, self.output_key)?;
+        usage.total_tokens += result.total_tokens;
+        usage.elapsed_time += result.elapsed_time;
+        Ok(translation)
+    }
+}
+
+// 切分后的一部分逐行发送, 单独一行译成多行时合并为一行
+struct LineRequest<'a, 'b> {
+    aligner: &'b Aligner<'a>,
+    context: Option<&'b TranslationContext>,
+}
+
+impl SplitRequest for LineRequest<'_, '_> {
+    type Item = str;
+
+    async fn request_part(&self, part: &[&str], usage: &mut Usage) -> Result<Option<Vec<String>>, String> {
+        let lines = non_empty_lines(&self.aligner.request(&part.join("\n"), self.context, usage).await?);
+        Ok((lines.len() == part.len()).then_some(lines))
+    }
+
+    async fn request_single(&self, line: &str, usage: &mut Usage) -> Result<String, String> {
+        Ok(non_empty_lines(&self.aligner.request(line, self.context, usage).await?).join(" "))
+    }
+}
+
+fn non_empty_lines(text: &str) -> Vec<String> {
+    text.lines().map(str::trim_end).filter(|line| !line.trim().is_empty()).map(str::to_string).collect()
+}
+
+// 发送给工作流的文本去掉首尾空行, 中间的空行保留
+fn trim_blank_lines(lines: &[&str]) -> String {
+    let first = lines.iter().position(|line| !line.is_empty()).unwrap_or(lines.len());
+    let last = lines.iter().rposition(|line| !line.is_empty()).map(|i| i + 1).unwrap_or(first);
+    lines[first..last].join("\n")
+}
@@ -1,6 +1,18 @@
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// 打印请求和事件数据, 用于调试工作流
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Input<'a> {
@@ -8,6 +20,11 @@ pub struct Input<'a> {
     source_text: String,
     source_lang: &'a str,
     term: &'a str,
+    // 前文的原文和已确认的译文, 只作为参考, 未开启时不发送
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context_source: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context_translation: Option<&'a str>,
 }
 
 impl<'a > Input<'a> {
@@ -17,8 +34,16 @@ impl<'a > Input<'a> {
             source_text,
             source_lang,
             term,
+            context_source: None,
+            context_translation: None,
         }
     }
+
+    pub fn with_context(mut self, source: &'a str, translation: &'a str) -> Self {
+        self.context_source = Some(source);
+        self.context_translation = Some(translation);
+        self
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -38,28 +63,25 @@ impl<'a> RequestData<'a> {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct WorkflowResponse {
-    data: Option<WorkflowData>,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-struct WorkflowData {
-    outputs: Option<Value>,
+// workflow_finished 事件中的输出和用量
+#[derive(Debug)]
+pub struct WorkflowResult {
+    pub outputs: Value,
+    pub total_tokens: u64,
+    pub elapsed_time: f64,
 }
 
 pub async fn run_workflow<'a>(
     api_key: &str,
     base_url: &str,
     request_data: &RequestData<'a>
-) -> Result<Option<Value>, String> {
+) -> Result<Option<WorkflowResult>, String> {
     let url = format!("{}/v1/workflows/run", base_url);
     let client = Client::new();
 
-    println!("工作流正在运行 {}\n", url);
-
-    if let Err(e) = log_request_data(request_data) {
-        return Err(e);
+    if is_verbose() {
+        println!("工作流正在运行 {}\n", url);
+        log_request_data(request_data)?;
     }
 
     let mut response = send_post_request(&client, &url, api_key, request_data).await?;
@@ -91,12 +113,13 @@ async fn send_post_request<'a>(
         .map_err(|e| e.to_string())
 }
 
-async fn process_response(response: &mut reqwest::Response) -> Result<Option<Value>, String> {
+async fn process_response(response: &mut reqwest::Response) -> Result<Option<WorkflowResult>, String> {
     let mut buffer = Vec::new();
 
     while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
         buffer.extend_from_slice(chunk.as_ref());
-        if let Some(pos) = find_event_data_position(&buffer) {
+        // 一个网络分块中可能包含多个事件
+        while let Some(pos) = find_event_data_position(&buffer) {
             let data = extract_data(&buffer, pos)?;
             if let Some(event_data) = process_event_data(data)? {
                 return Ok(Some(event_data));
@@ -117,19 +140,28 @@ fn extract_data(buffer: &[u8], pos: usize) -> Result<&str, String> {
     std::str::from_utf8(data).map_err(|e| e.to_string())
 }
 
-fn process_event_data(data: &str) -> Result<Option<Value>, String> {
+fn process_event_data(data: &str) -> Result<Option<WorkflowResult>, String> {
     if let Some(data_content) = data.strip_prefix("data: ") {
         let event_data = data_content.trim().to_string();
-        println!("Received event data: {}\n", event_data);
+        if is_verbose() {
+            println!("Received event data: {}\n", event_data);
+        }
 
         let json_data = serde_json::from_str::<Value>(&event_data)
-            .map_err(|e| format!("event data转换失败: {}", e.to_string()))?;
+            .map_err(|e| format!("event data转换失败: {}", e))?;
 
         if let Some(event) = json_data.get("event").and_then(|e| e.as_str()) {
             if event == "workflow_finished" {
-                if let Some(outputs) = json_data.get("data").and_then(|d| d.get("outputs")) {
-                    println!("Workflow finished with outputs: {}\n", outputs);
-                    return Ok(Some(outputs.clone()));
+                let data = json_data.get("data");
+                if let Some(outputs) = data.and_then(|d| d.get("outputs")) {
+                    if is_verbose() {
+                        println!("Workflow finished with outputs: {}\n", outputs);
+                    }
+                    return Ok(Some(WorkflowResult {
+                        outputs: outputs.clone(),
+                        total_tokens: data.and_then(|d| d.get("total_tokens")).and_then(Value::as_u64).unwrap_or(0),
+                        elapsed_time: data.and_then(|d| d.get("elapsed_time")).and_then(Value::as_f64).unwrap_or(0.0),
+                    }));
                 }
             }
         }
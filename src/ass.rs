@@ -0,0 +1,203 @@
+use crate::document::{placeholder, Document, Unit};
+use std::collections::HashMap;
+
+// 没有 Format 行时按 ASS 的默认字段: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text
+const DEFAULT_FIELDS: usize = 10;
+
+// ASS/SSA 字幕: 只翻译 [Events] 中 Dialogue 事件的 Text 字段, 其余各行原样保留
+pub struct Ass {
+    lines: Vec<Line>,
+    keep_original: bool,
+}
+
+enum Line {
+    Raw(String),
+    // prefix 是 Text 字段之前的部分, 包括 "Dialogue:" 和各字段后的逗号
+    Dialogue { prefix: String, text: String },
+}
+
+pub fn parse(source: &str, keep_original: bool) -> Result<Ass, String> {
+    let mut lines = Vec::new();
+    let mut in_events = false;
+    let mut fields = DEFAULT_FIELDS;
+
+    for (number, line) in source.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_events = trimmed.eq_ignore_ascii_case("[events]");
+        } else if in_events {
+            if let Some(format) = trimmed.strip_prefix("Format:") {
+                fields = format.split(',').filter(|field| !field.trim().is_empty()).count();
+            } else if let Some(rest) = line.strip_prefix("Dialogue:") {
+                // Text 是最后一个字段, 其中可以有逗号; 只有 Text 一个字段时冒号后面都是 Text
+                let start = match fields.checked_sub(2) {
+                    None => line.len() - rest.trim_start().len(),
+                    Some(skip) => line.match_indices(',')
+                        .nth(skip)
+                        .map(|(i, _)| i + 1)
+                        .ok_or_else(|| format!("第 {} 行的 Dialogue 字段不足 {} 个", number + 1, fields))?,
+                };
+                lines.push(Line::Dialogue { prefix: line[..start].to_string(), text: line[start..].to_string() });
+                continue;
+            }
+        }
+        lines.push(Line::Raw(line.to_string()));
+    }
+
+    if !lines.iter().any(|line| matches!(line, Line::Dialogue { .. })) {
+        return Err("没有找到 Dialogue 事件".to_string());
+    }
+    Ok(Ass { lines, keep_original })
+}
+
+// 覆盖标签 {...}、换行 \N \n 和硬空格 \h 替换为占位符, 相邻的合并为一个
+// {\p1} 之后到 {\p0} 之前是绘图命令, 也一并替换
+fn mask_text(text: &str) -> (String, Vec<String>) {
+    let mut masked = String::new();
+    let mut tags: Vec<String> = Vec::new();
+    let mut tag = String::new();
+    let mut drawing = false;
+    let mut rest = text;
+
+    while let Some(c) = rest.chars().next() {
+        let len = if c == '{' {
+            let len = rest.find('}').map_or(rest.len(), |end| end + 1);
+            if let Some(level) = drawing_level(&rest[..len]) {
+                drawing = level > 0;
+            }
+            tag.push_str(&rest[..len]);
+            len
+        } else if drawing {
+            let len = rest.find('{').unwrap_or(rest.len());
+            tag.push_str(&rest[..len]);
+            len
+        } else if rest.starts_with("\\N") || rest.starts_with("\\n") || rest.starts_with("\\h") {
+            tag.push_str(&rest[..2]);
+            2
+        } else {
+            if !tag.is_empty() {
+                masked.push_str(&placeholder(tags.len()));
+                tags.push(std::mem::take(&mut tag));
+            }
+            masked.push(c);
+            c.len_utf8()
+        };
+        rest = &rest[len..];
+    }
+    if !tag.is_empty() {
+        masked.push_str(&placeholder(tags.len()));
+        tags.push(tag);
+    }
+    (masked, tags)
+}
+
+// 覆盖标签中最后一个 \p 的值
+fn drawing_level(block: &str) -> Option<u32> {
+    block.match_indices("\\p")
+        .filter_map(|(i, _)| {
+            let digits: String = block[i + 2..].chars().take_while(char::is_ascii_digit).collect();
+            digits.parse().ok()
+        })
+        .last()
+}
+
+impl Document for Ass {
+    // 按 Dialogue 事件在文件中的位置编号
+    fn units(&self) -> Vec<Unit> {
+        self.lines.iter()
+            .filter_map(|line| match line {
+                Line::Dialogue { text, .. } => Some(text),
+                Line::Raw(_) => None,
+            })
+            .enumerate()
+            .map(|(i, text)| {
+                let (text, tags) = mask_text(text);
+                Unit { id: (i + 1).to_string(), text, tags }
+            })
+            .collect()
+    }
+
+    fn render(&self, translations: &HashMap<String, String>) -> String {
+        let mut output = String::new();
+        let mut number = 0;
+        for line in &self.lines {
+            match line {
+                Line::Raw(raw) => output.push_str(raw),
+                Line::Dialogue { prefix, text } => {
+                    number += 1;
+                    match translations.get(&number.to_string()) {
+                        Some(translation) => {
+                            if self.keep_original {
+                                output.push_str(&format!("Comment:{}{}\n", &prefix["Dialogue:".len()..], text));
+                            }
+                            output.push_str(prefix);
+                            output.push_str(translation);
+                        }
+                        None => {
+                            output.push_str(prefix);
+                            output.push_str(text);
+                        }
+                    }
+                }
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    // 一个事件只能占一行, 译文中的换行改为 \N; 花括号会被当作覆盖标签, 改为全角
+    fn escape(&self, translation: &str) -> String {
+        translation.lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\\N")
+            .replace('{', "｛")
+            .replace('}', "｝")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "[Script Info]\nTitle: 示例\n\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nDialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{\\i1}你好,{\\i0}世界\\N第二行\nComment: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,注释\nDialogue: 0,0:00:03.00,0:00:04.00,Sign,,0,0,0,,{\\p1}m 0 0 l 10 10{\\p0}标志\n";
+
+    #[test]
+    fn masks_override_tags_line_breaks_and_drawings() {
+        let units = parse(SOURCE, false).unwrap().units();
+        assert_eq!(units.len(), 2);
+        assert_eq!(units[0].text, "[[0]]你好,[[1]]世界[[2]]第二行");
+        assert_eq!(units[1].text, "[[0]]标志");
+        assert_eq!(units[1].tags, vec!["{\\p1}m 0 0 l 10 10{\\p0}"]);
+    }
+
+    #[test]
+    fn restores_override_tags_in_translated_order() {
+        let ass = parse("[Events]\nFormat: Start, Text\nDialogue: 0:00:01.00,{\\b1}你好{\\b0}\\N世界\n", false).unwrap();
+        assert_eq!(ass.units()[0].text, "[[0]]你好[[1]]世界");
+        let translations = ass.restore_tags(&HashMap::from([("1".to_string(), "World [[0]]Hello[[1]]".to_string())]));
+        assert_eq!(ass.render(&translations), "[Events]\nFormat: Start, Text\nDialogue: 0:00:01.00,World {\\b1}Hello{\\b0}\\N\n");
+    }
+
+    #[test]
+    fn keeps_original_as_comment() {
+        let ass = parse("[Events]\nFormat: Start, Text\nDialogue: 0:00:01.00,原文\n", true).unwrap();
+        let translations = HashMap::from([("1".to_string(), "译文".to_string())]);
+        assert_eq!(ass.render(&translations), "[Events]\nFormat: Start, Text\nComment: 0:00:01.00,原文\nDialogue: 0:00:01.00,译文\n");
+    }
+
+    #[test]
+    fn treats_whole_dialogue_as_text_with_single_format_field() {
+        let ass = parse("[Events]\nFormat: Text\nDialogue: 原文, 有逗号\n", false).unwrap();
+        assert_eq!(ass.units()[0].text, "原文, 有逗号");
+        let translations = HashMap::from([("1".to_string(), "译文".to_string())]);
+        assert_eq!(ass.render(&translations), "[Events]\nFormat: Text\nDialogue: 译文\n");
+        assert!(parse("[Events]\nFormat:\nDialogue: 原文\n", false).is_ok());
+    }
+
+    #[test]
+    fn rejects_dialogue_with_missing_fields() {
+        assert!(parse("[Events]\nFormat: Start, End, Text\nDialogue: 0:00:01.00\n", false).is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::file_operations::write_bytes_overwrite;
+use crate::pipeline::Job;
+use crate::state::{ChunkStatus, JobState};
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+// 超过这个长度的行不视为章节标题, 避免正文中恰好以 "第三章" 开头的句子被切开
+const MAX_HEADING_CHARS: usize = 40;
+const MAX_TITLE_CHARS: usize = 40;
+
+// 内置的章节标题格式
+const PRESETS: &[(&str, &str)] = &[
+    ("zh", r"^第[0-9０-９零〇一二两三四五六七八九十百千万]+[章回节節卷]"),
+    ("ja", r"^第[0-9０-９零〇一二三四五六七八九十百千]+[話话]"),
+    ("en", r"(?i)^chapter\s+([0-9]+|[ivxlcdm]+)\b"),
+];
+
+// 项目清单中的 chapters 设置, presets 和 patterns 都为空时不按章节切分
+#[derive(Deserialize, Default)]
+pub struct ChapterSettings {
+    #[serde(default)]
+    pub presets: Vec<String>,
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    #[serde(default)]
+    pub split_files: bool,
+}
+
+impl ChapterSettings {
+    pub fn matcher(&self) -> Result<Option<ChapterMatcher>, String> {
+        if self.presets.is_empty() && self.patterns.is_empty() {
+            return Ok(None);
+        }
+        ChapterMatcher::new(&self.presets, &self.patterns).map(Some)
+    }
+}
+
+pub struct ChapterMatcher {
+    patterns: Vec<Regex>,
+}
+
+impl ChapterMatcher {
+    pub fn new(presets: &[String], patterns: &[String]) -> Result<Self, String> {
+        let mut compiled = Vec::new();
+        for name in presets {
+            let (_, pattern) = PRESETS.iter()
+                .find(|(preset, _)| preset == name)
+                .ok_or_else(|| format!("无效的章节标题格式: {}, 可以使用 zh、ja 或 en", name))?;
+            compiled.push(Regex::new(pattern).unwrap());
+        }
+        for pattern in patterns {
+            compiled.push(Regex::new(pattern).map_err(|e| format!("无效的章节标题正则 {}: {}", pattern, e))?);
+        }
+        Ok(ChapterMatcher { patterns: compiled })
+    }
+
+    // 去掉首尾空白后匹配, 标题前的全角空格缩进也可以匹配
+    pub fn is_heading(&self, line: &str) -> bool {
+        let line = line.trim();
+        !line.is_empty()
+            && line.chars().count() <= MAX_HEADING_CHARS
+            && self.patterns.iter().any(|pattern| pattern.is_match(line))
+    }
+}
+
+// 按章节标题所在的块把合并的输出拆成每章一个文件, 放在与输出文件同名的目录中
+// 第一个标题之前的内容写入 000, 每次都根据记录重新生成并记下文件名, 返回写入的文件数
+pub async fn write_chapter_files(job: &Job, state: &mut JobState) -> Result<usize, String> {
+    let output = fs::read(job.output_path()).unwrap_or_default();
+    let file_name = Path::new(&job.output_file_name);
+    let stem = file_name.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = file_name.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+    let folder = Path::new(&job.output_dir).join(stem.as_ref());
+
+    let mut chapters: Vec<(String, u64, u64)> = Vec::new();
+    let mut number = 0;
+    for chunk in state.chunks.iter().filter(|chunk| matches!(chunk.status, ChunkStatus::Done | ChunkStatus::Failed)) {
+        let end = chunk.output_offset + chunk.output_len;
+        match chapters.last_mut() {
+            Some(chapter) if !chunk.heading => chapter.2 = end,
+            _ => {
+                number += usize::from(chunk.heading);
+                let title = chapter_title(job, &output, chunk.output_offset, end, chunk.heading && chunk.status == ChunkStatus::Done);
+                chapters.push((format!("{:03}{}", number, title), chunk.output_offset, end));
+            }
+        }
+    }
+
+    // 重新翻译后标题可能改变, 先删除上次生成而这次不再生成的章节文件, 目录中的其他文件不受影响
+    let names: Vec<String> = chapters.iter().map(|(name, _, _)| format!("{}{}", name, extension)).collect();
+    for name in state.chapter_files.iter().filter(|name| !names.contains(name)) {
+        let _ = fs::remove_file(folder.join(name));
+    }
+
+    for ((_, start, end), name) in chapters.iter().zip(&names) {
+        let Some(bytes) = output.get(*start as usize..*end as usize) else {
+            return Err(format!("输出文件 {} 与记录不一致", job.output_path()));
+        };
+        // 文件开头的 BOM 只在第一块中
+        let mut content = if *start > 0 { job.output_encoding.encode("", true).0 } else { Vec::new() };
+        content.extend_from_slice(bytes);
+        write_bytes_overwrite(&folder.to_string_lossy(), name, &content)
+            .await
+            .map_err(|e| format!("无法写入章节文件: {}", e))?;
+    }
+    state.chapter_files = names;
+    Ok(chapters.len())
+}
+
+// 用译文中的标题作为文件名的一部分, 去掉文件名中不能使用的字符
+fn chapter_title(job: &Job, output: &[u8], start: u64, end: u64, heading: bool) -> String {
+    if !heading {
+        return String::new();
+    }
+    let text = output.get(start as usize..end as usize).map(|bytes| job.output_encoding.decode(bytes)).unwrap_or_default();
+    let title: String = text.trim()
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_control() || "/\\:*?\"<>|".contains(c) { '_' } else { c })
+        .take(MAX_TITLE_CHARS)
+        .collect();
+    let title = title.trim();
+    if title.is_empty() {
+        String::new()
+    } else {
+        format!("_{}", title)
+    }
+}
@@ -0,0 +1,151 @@
+use crate::chapters::ChapterMatcher;
+use crate::tokenizer::{default_tokenizer, tokenizer_from_name, Tokenizer};
+use serde::Deserialize;
+use std::sync::Arc;
+
+// 按预算切分时留给译文之外内容的最小空间, 低于该值说明术语表或提示词太长
+const MIN_CHUNK_TOKENS: usize = 64;
+
+// 除 lines 外都按 tokens 预算装入, 区别在于装入的单位: 行、空行分隔的段落、句子
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkMode {
+    #[default]
+    Lines,
+    Tokens,
+    Paragraphs,
+    Sentences,
+}
+
+impl ChunkMode {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "lines" => Ok(ChunkMode::Lines),
+            "tokens" => Ok(ChunkMode::Tokens),
+            "paragraphs" => Ok(ChunkMode::Paragraphs),
+            "sentences" => Ok(ChunkMode::Sentences),
+            _ => Err(format!("无效的切分方式: {}, 可以使用 lines、tokens、paragraphs 或 sentences", name)),
+        }
+    }
+}
+
+// max_tokens 是一次请求的输入上限, 术语表和工作流提示词每次都会发送, 先从中扣除
+// chapters 不为空时块不跨越章节, 章节标题单独成块
+#[derive(Clone)]
+pub struct ChunkingOptions {
+    pub mode: ChunkMode,
+    pub num_lines: usize,
+    pub max_tokens: usize,
+    pub prompt_tokens: usize,
+    pub tokenizer: Arc<dyn Tokenizer>,
+    pub chapters: Option<Arc<ChapterMatcher>>,
+}
+
+impl ChunkingOptions {
+    pub fn lines(num_lines: usize, prompt_tokens: usize) -> Self {
+        ChunkingOptions {
+            mode: ChunkMode::Lines,
+            num_lines,
+            max_tokens: 0,
+            prompt_tokens,
+            tokenizer: default_tokenizer(),
+            chapters: None,
+        }
+    }
+
+    pub fn packed(mode: ChunkMode, max_tokens: usize, prompt_tokens: usize, tokenizer: Option<&str>) -> Result<Self, String> {
+        Ok(ChunkingOptions {
+            mode,
+            num_lines: 0,
+            max_tokens,
+            prompt_tokens,
+            tokenizer: match tokenizer {
+                Some(name) => tokenizer_from_name(name)?,
+                None => default_tokenizer(),
+            },
+            chapters: None,
+        })
+    }
+
+    // 每个任务的术语表不同, 留给原文的预算也不同
+    pub fn strategy_for(&self, term: &str) -> Result<ChunkStrategy, String> {
+        match self.mode {
+            ChunkMode::Lines => Ok(ChunkStrategy::Lines(self.num_lines.max(1))),
+            mode => {
+                let headroom = self.tokenizer.count(term) + self.prompt_tokens;
+                let budget = self.max_tokens.saturating_sub(headroom);
+                if budget < MIN_CHUNK_TOKENS {
+                    return Err(format!(
+                        "max_tokens {} 太小, 术语表和提示词约占 {} tokens, 留给原文的不足 {}",
+                        self.max_tokens, headroom, MIN_CHUNK_TOKENS
+                    ));
+                }
+                Ok(ChunkStrategy::Packed { mode, budget, tokenizer: Arc::clone(&self.tokenizer) })
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum ChunkStrategy {
+    Lines(usize),
+    Packed { mode: ChunkMode, budget: usize, tokenizer: Arc<dyn Tokenizer> },
+}
+
+// 在 budget 以内找到切分位置, 尽量切在句末, 其次切在空白之后, 返回字节位置, 至少包含一个字符
+pub fn split_point(text: &str, budget: usize, tokenizer: &dyn Tokenizer) -> usize {
+    let boundaries: Vec<usize> = text.char_indices().map(|(i, _)| i).skip(1).chain([text.len()]).collect();
+
+    let fits = boundaries.partition_point(|&end| tokenizer.count(&text[..end]) <= budget);
+    let limit = boundaries[fits.saturating_sub(1)];
+
+    if let Some(end) = last_sentence_end(&text[..limit]).filter(|&end| end >= limit / 2) {
+        return end;
+    }
+    text[..limit].char_indices()
+        .rev()
+        .find(|&(_, c)| c.is_whitespace())
+        .map(|(i, c)| i + c.len_utf8())
+        .unwrap_or(limit)
+}
+
+pub fn last_sentence_end(text: &str) -> Option<usize> {
+    sentence_ends(text).last().copied()
+}
+
+// 所有句子结束的位置: 中日文的 。！？ 和 」』, 西文的 .!? 后面需要跟空格或位于行尾
+// 紧跟的右引号、右括号和空格归入前一句
+pub fn sentence_ends(text: &str) -> Vec<usize> {
+    let mut ends = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let is_end = match c {
+            '。' | '！' | '？' | '」' | '』' => true,
+            '.' | '!' | '?' => chars.peek().is_none_or(|&(_, next)| next.is_whitespace()),
+            _ => false,
+        };
+        if !is_end {
+            continue;
+        }
+
+        let mut end = i + c.len_utf8();
+        while let Some(&(j, next)) = chars.peek() {
+            if !matches!(next, '」' | '』' | '）' | ')' | '"' | '\'' | '”' | '’' | ' ' | '\u{3000}') {
+                break;
+            }
+            end = j + next.len_utf8();
+            chars.next();
+        }
+        ends.push(end);
+    }
+    ends
+}
+
+// 用于显示的行范围, 从1开始; 行内切开的块可能只覆盖一行的一部分
+pub fn line_range(start_line: usize, end_line: usize) -> String {
+    if end_line <= start_line + 1 {
+        format!("{}", start_line + 1)
+    } else {
+        format!("{}-{}", start_line + 1, end_line)
+    }
+}
@@ -1,37 +1,149 @@
+use std::fmt;
 use std::fs;
 use std::path::Path;
+use std::process::Command;
 
-use serde_json;
-use serde_yaml;
 use serde::{Deserialize, Serialize};
 
+// 用户设置, 翻译进度保存在 state 目录
 #[derive(Serialize, Deserialize)]
 pub struct ConfigData {
     pub target_lang: String,
     pub source_lang: String,
-    pub history_lines: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
 pub struct APIConfig {
     pub(crate) api_key: String,
     pub(crate) base_url: String,
 }
 
-pub fn load_api_config(config_path: &str) -> Result<APIConfig, String> {
+// api_key 不应出现在日志中, 因此不派生 Debug
+impl fmt::Debug for APIConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("APIConfig")
+            .field("api_key", &"<redacted>")
+            .field("base_url", &self.base_url)
+            .finish()
+    }
+}
+
+// user.yaml 中的原始配置, api_key 可以来自明文、环境变量、密钥文件或命令输出
+#[derive(Deserialize)]
+struct APIConfigFile {
+    base_url: String,
+    api_key: Option<String>,
+    api_key_env: Option<String>,
+    api_key_file: Option<String>,
+    api_key_command: Option<String>,
+}
+
+// profile 对应 user.yaml 中 profiles 下的同名配置, 为 None 时使用顶层配置
+pub fn load_api_config(config_path: &str, profile: Option<&str>) -> Result<APIConfig, String> {
     let yaml_str = fs::read_to_string(config_path)
         .map_err(|_| format!("无法读取配置文件: {}", config_path))?;
 
-    let config: APIConfig = serde_yaml::from_str(&yaml_str)
+    let mut value: serde_yaml::Value = serde_yaml::from_str(&yaml_str)
+        .map_err(|_| "解析配置文件失败".to_string())?;
+
+    if let Some(profile) = profile {
+        value = value
+            .get("profiles")
+            .and_then(|profiles| profiles.get(profile))
+            .cloned()
+            .ok_or_else(|| format!("配置文件中不存在profile: {}", profile))?;
+    }
+
+    let config: APIConfigFile = serde_yaml::from_value(value)
         .map_err(|_| "解析配置文件失败".to_string())?;
 
-    Ok(config)
+    let api_key = resolve_api_key(&config)?;
+
+    Ok(APIConfig {
+        api_key,
+        base_url: config.base_url,
+    })
+}
+
+fn resolve_api_key(config: &APIConfigFile) -> Result<String, String> {
+    let api_key = if let Some(name) = &config.api_key_env {
+        std::env::var(name).map_err(|_| format!("环境变量未设置: {}", name))?
+    } else if let Some(path) = &config.api_key_file {
+        read_secret_file(path)?
+    } else if let Some(command) = &config.api_key_command {
+        run_secret_command(command)?
+    } else if let Some(api_key) = &config.api_key {
+        api_key.clone()
+    } else {
+        return Err("未配置api_key, 请设置api_key、api_key_env、api_key_file或api_key_command".to_string());
+    };
+
+    let api_key = api_key.trim().to_string();
+    if api_key.is_empty() {
+        return Err("api_key为空".to_string());
+    }
+    Ok(api_key)
+}
+
+fn read_secret_file(path: &str) -> Result<String, String> {
+    check_secret_permissions(path)?;
+    let content = fs::read_to_string(path)
+        .map_err(|_| format!("无法读取密钥文件: {}", path))?;
+    // 只取第一行, 方便在文件中追加注释
+    Ok(content.lines().next().unwrap_or("").to_string())
+}
+
+#[cfg(unix)]
+fn check_secret_permissions(path: &str) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = fs::metadata(path)
+        .map_err(|_| format!("无法读取密钥文件: {}", path))?;
+    let mode = metadata.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(format!(
+            "密钥文件权限过宽({:o}), 请执行 chmod 600 {}",
+            mode & 0o777,
+            path
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_secret_permissions(path: &str) -> Result<(), String> {
+    if !Path::new(path).is_file() {
+        return Err(format!("无法读取密钥文件: {}", path));
+    }
+    Ok(())
+}
+
+fn run_secret_command(command: &str) -> Result<String, String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .map_err(|e| format!("无法执行api_key_command: {}", e))?;
+
+    // 不输出stdout, 以免密钥出现在日志中
+    if !output.status.success() {
+        return Err(format!("api_key_command执行失败: {}", output.status));
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| "api_key_command输出不是有效的UTF-8".to_string())?;
+    Ok(stdout.lines().next().unwrap_or("").to_string())
 }
 
 pub fn load_config_from_file(input_file_path: &str) -> Option<ConfigData> {
+    // 与创建配置时相同, 按去掉扩展名的文件名查找
+    let file_stem = Path::new(input_file_path).file_stem()?.to_string_lossy();
+    let config_file_name = format!("{}.json", file_stem);
+    load_config_data(&config_file_name)
+}
+
+pub fn load_config_data(config_file_name: &str) -> Option<ConfigData> {
     let config_dir = "config";
-    let file_name = Path::new(input_file_path).file_name().unwrap().to_str().unwrap();
-    let config_file_name = format!("{}.json", file_name.strip_suffix(".txt").unwrap());
     let config_file_path = Path::new(config_dir).join(config_file_name);
 
     if config_file_path.exists() {
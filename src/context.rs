@@ -0,0 +1,97 @@
+use crate::file_operations::read_source_range;
+use crate::pipeline::Job;
+use crate::state::{ChunkStatus, JobState};
+use std::collections::VecDeque;
+use std::fs;
+
+// 作为额外输入发送给工作流的上文, 只供参考, 不需要翻译
+pub struct TranslationContext {
+    pub source: String,
+    pub translation: String,
+}
+
+// 最近按顺序写入输出的若干行原文和译文, next_index 是下一个待写入的块
+pub struct ContextWindow {
+    pub next_index: usize,
+    lines: usize,
+    source: VecDeque<String>,
+    translation: VecDeque<String>,
+}
+
+impl ContextWindow {
+    // 继续翻译时从已写入的块中恢复上文
+    pub async fn load(job: &Job, state: &JobState, lines: usize) -> Self {
+        let mut window = ContextWindow {
+            next_index: state.next_index(),
+            lines,
+            source: VecDeque::new(),
+            translation: VecDeque::new(),
+        };
+        if lines == 0 {
+            return window;
+        }
+
+        let output = fs::read(job.output_path()).unwrap_or_default();
+        let mut recent = Vec::new();
+        let mut source_lines = 0;
+        for chunk in state.chunks.iter().rev() {
+            if chunk.status != ChunkStatus::Done || source_lines >= lines {
+                break;
+            }
+            let start = chunk.output_offset as usize;
+            let Some(translation) = output.get(start..start + chunk.output_len as usize) else {
+                break;
+            };
+            let Ok(source) = read_source_range(&job.source_path, job.source_encoding, chunk.start_byte, chunk.end_byte).await else {
+                break;
+            };
+            source_lines += source.lines().count();
+            recent.push((source, job.output_encoding.decode(translation)));
+        }
+
+        for (source, translation) in recent.iter().rev() {
+            window.push(source, translation);
+        }
+        window
+    }
+
+    // 失败的块没有可用的译文, 上文从下一块重新开始积累
+    pub fn commit(&mut self, source: &str, translation: Option<&str>) {
+        self.next_index += 1;
+        match translation {
+            Some(translation) => self.push(source, translation),
+            None => {
+                self.source.clear();
+                self.translation.clear();
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Option<TranslationContext> {
+        if self.source.is_empty() {
+            return None;
+        }
+        Some(TranslationContext {
+            source: self.source.iter().map(String::as_str).collect::<Vec<_>>().join("\n"),
+            translation: self.translation.iter().map(String::as_str).collect::<Vec<_>>().join("\n"),
+        })
+    }
+
+    fn push(&mut self, source: &str, translation: &str) {
+        if self.lines == 0 {
+            return;
+        }
+        push_lines(&mut self.source, source, self.lines);
+        push_lines(&mut self.translation, translation, self.lines);
+    }
+}
+
+// 只保留最后 limit 个非空行
+fn push_lines(buffer: &mut VecDeque<String>, text: &str, limit: usize) {
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        buffer.push_back(line.to_string());
+    }
+    while buffer.len() > limit {
+        buffer.pop_front();
+    }
+}
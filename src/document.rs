@@ -0,0 +1,670 @@
+use crate::align::{translate_split, Repair, SplitRequest, DEFAULT_ALIGN_RETRIES};
+use crate::chunking::ChunkStrategy;
+use crate::config::APIConfig;
+use crate::context::TranslationContext;
+use crate::encoding::decode;
+use crate::file_operations::{write_bytes_overwrite, write_json_overwrite, STATE_DIR};
+use crate::pipeline::{extract_translation, process_task, Job, JobReport, PipelineOptions, RunSummary};
+use crate::shutdown::{self, Shutdown};
+use crate::state::Usage;
+use crate::ass;
+use crate::epub;
+use crate::html;
+use crate::markdown;
+use crate::po;
+use crate::srt;
+use crate::vtt;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+// 每段译文前的标记行, 例如 [#12]
+static MARKER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^\s*\[#([^\]\s]+)\]\s*(.*)$").unwrap());
+// 替换标签的占位符, 例如 [[0]]
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\[\[(\d+)\]\]").unwrap());
+
+// 按扩展名决定源文件的格式, 纯文本之外的格式解析后只翻译其中的文本
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Text,
+    Srt,
+    Vtt,
+    Ass,
+    Ssa,
+    Markdown,
+    Html,
+    Xhtml,
+    Epub,
+    Po,
+}
+
+impl Format {
+    pub fn from_path(path: &str) -> Self {
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "srt" => Format::Srt,
+            "vtt" => Format::Vtt,
+            "ass" => Format::Ass,
+            "ssa" => Format::Ssa,
+            "md" | "markdown" => Format::Markdown,
+            "html" | "htm" => Format::Html,
+            "xhtml" => Format::Xhtml,
+            "epub" => Format::Epub,
+            "po" | "pot" => Format::Po,
+            _ => Format::Text,
+        }
+    }
+
+    // 输出文件使用的扩展名
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Text => "txt",
+            Format::Srt => "srt",
+            Format::Vtt => "vtt",
+            Format::Ass => "ass",
+            Format::Ssa => "ssa",
+            Format::Markdown => "md",
+            Format::Html => "html",
+            Format::Xhtml => "xhtml",
+            Format::Epub => "epub",
+            Format::Po => "po",
+        }
+    }
+
+    fn parse(&self, source: &str, target_lang: &str, options: &DocumentOptions) -> Result<Box<dyn Document>, String> {
+        match self {
+            Format::Text => Err("纯文本文件不需要解析".to_string()),
+            Format::Srt => Ok(Box::new(srt::parse(source)?)),
+            Format::Vtt => Ok(Box::new(vtt::parse(source)?)),
+            Format::Ass | Format::Ssa => Ok(Box::new(ass::parse(source, options.keep_original)?)),
+            Format::Markdown => Ok(Box::new(markdown::parse(source, &options.front_matter))),
+            Format::Html | Format::Xhtml => Ok(Box::new(html::parse(source))),
+            Format::Epub => Err("EPUB 需要按压缩包读取".to_string()),
+            Format::Po => Ok(Box::new(po::parse(source, target_lang, options)?)),
+        }
+    }
+}
+
+// 各格式的设置, 项目清单中写在 documents 下
+// keep_original 为 true 时 ASS 字幕把原文保留为注释事件, 方便校对和卡拉OK特效
+// front_matter 是 Markdown 前置元数据中需要翻译的字段, 默认都不翻译
+// include_fuzzy 为 true 时 PO 文件中标记为 fuzzy 的条目也重新翻译, mark_fuzzy 为 true 时译文标记为 fuzzy 等待校对
+#[derive(Deserialize, Default, Clone)]
+pub struct DocumentOptions {
+    #[serde(default)]
+    pub keep_original: bool,
+    #[serde(default)]
+    pub front_matter: Vec<String>,
+    #[serde(default)]
+    pub include_fuzzy: bool,
+    #[serde(default)]
+    pub mark_fuzzy: bool,
+}
+
+// 文件中需要翻译的一段文本, id 在文件内唯一, 用于把译文填回原来的位置
+// 需要原样保留的标签在 text 中替换为占位符, 原文保存在 tags 中
+pub struct Unit {
+    pub id: String,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+impl Unit {
+    pub fn new(id: String, text: String) -> Self {
+        Unit { id, text, tags: Vec::new() }
+    }
+
+    // 还原标签后的原文
+    fn source(&self) -> String {
+        unmask(&self.text, &self.tags)
+    }
+
+    // 去掉占位符后没有文字的段落不需要翻译
+    fn is_blank(&self) -> bool {
+        PLACEHOLDER.replace_all(&self.text, "").trim().is_empty()
+    }
+}
+
+// 解析后的文件, 渲染时没有译文的段落保留原文
+// escape 在还原标签之前处理译文, 例如转义该格式中有特殊含义的字符
+// restore_tags 把译文中的占位符换回标签, 标签本身也有译文时 (例如 HTML 的 alt 属性) 按占位符序号替换
+// package 用于 EPUB 等压缩包, 直接生成输出文件的内容, 不经过换行符和编码转换
+// note 是段落的说明, 例如 PO 文件的 msgctxt 和注释, 随所在的批次作为上文发送
+pub trait Document: Send + Sync {
+    fn units(&self) -> Vec<Unit>;
+    fn render(&self, translations: &HashMap<String, String>) -> String;
+
+    fn note(&self, _id: &str) -> Option<String> {
+        None
+    }
+
+    fn package(&self, _translations: &HashMap<String, String>) -> Option<Result<Vec<u8>, String>> {
+        None
+    }
+
+    fn escape(&self, translation: &str) -> String {
+        translation.to_string()
+    }
+
+    fn restore_tags(&self, translations: &HashMap<String, String>) -> HashMap<String, String> {
+        self.units()
+            .into_iter()
+            .filter_map(|unit| Some((unit.id.clone(), unmask(translations.get(&unit.id)?, &unit.tags))))
+            .collect()
+    }
+}
+
+// 把匹配 pattern 的标签替换为 [[n]], 返回替换后的文本和标签原文
+pub fn mask(text: &str, pattern: &Regex) -> (String, Vec<String>) {
+    let mut tags = Vec::new();
+    let masked = pattern.replace_all(text, |captures: &regex::Captures| {
+        tags.push(captures[0].to_string());
+        placeholder(tags.len() - 1)
+    });
+    (masked.into_owned(), tags)
+}
+
+pub fn placeholder(index: usize) -> String {
+    format!("[[{}]]", index)
+}
+
+pub fn unmask(text: &str, tags: &[String]) -> String {
+    PLACEHOLDER.replace_all(text, |captures: &regex::Captures| {
+        let index: usize = captures[1].parse().unwrap_or(usize::MAX);
+        tags.get(index).cloned().unwrap_or_else(|| captures[0].to_string())
+    }).into_owned()
+}
+
+// 每个占位符必须恰好出现一次, 顺序可以改变
+fn check_placeholders(text: &str, count: usize) -> Result<(), String> {
+    let mut seen = vec![0; count];
+    for captures in PLACEHOLDER.captures_iter(text) {
+        match captures[1].parse::<usize>().ok().and_then(|index| seen.get_mut(index)) {
+            Some(times) => *times += 1,
+            None => return Err(format!("多出的占位符 {}", &captures[0])),
+        }
+    }
+    match seen.iter().position(|&times| times != 1) {
+        Some(index) => Err(format!("占位符 [[{}]] 出现了 {} 次", index, seen[index])),
+        None => Ok(()),
+    }
+}
+
+// 结构化文件的翻译进度, 按 id 记录译文和原文的 SHA-256, 原文改变的段落会重新翻译
+// 译文中的标签保存为占位符, 写入输出文件时再还原
+#[derive(Serialize, Deserialize)]
+pub struct DocumentState {
+    pub source_path: String,
+    pub source_lang: String,
+    pub target_lang: String,
+    pub output_path: String,
+    pub units: BTreeMap<String, UnitRecord>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub failed: BTreeMap<String, String>,
+    #[serde(default)]
+    pub total_tokens: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnitRecord {
+    pub hash: String,
+    pub translation: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repair: Option<Repair>,
+}
+
+impl DocumentState {
+    pub fn load(job: &Job) -> Result<Self, String> {
+        let path = Path::new(STATE_DIR).join(&job.state_file_name);
+        if !path.exists() {
+            return Ok(DocumentState {
+                source_path: job.source_path.clone(),
+                source_lang: job.config_data.source_lang.clone(),
+                target_lang: job.config_data.target_lang.clone(),
+                output_path: job.output_path(),
+                units: BTreeMap::new(),
+                failed: BTreeMap::new(),
+                total_tokens: 0,
+            });
+        }
+
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("无法读取任务状态 {}: {}", job.state_file_name, e))?;
+        serde_json::from_str(&content).map_err(|e| format!("无法读取任务状态 {}: {}", job.state_file_name, e))
+    }
+
+    async fn save(&self, job: &Job) -> Result<(), String> {
+        write_json_overwrite(STATE_DIR, &job.state_file_name, self)
+            .await
+            .map_err(|e| format!("无法保存任务状态: {}", e))
+    }
+
+    // 只取原文没有改变的译文, 其中的标签仍是占位符
+    fn translations(&self, units: &[Unit]) -> HashMap<String, String> {
+        units.iter()
+            .filter_map(|unit| {
+                let record = self.units.get(&unit.id)?;
+                (record.hash == hash(&unit.source())).then(|| (unit.id.clone(), record.translation.clone()))
+            })
+            .collect()
+    }
+
+    pub fn print_summary(&self) {
+        println!("{} ({}→{}) -> {}", self.source_path, self.source_lang, self.target_lang, self.output_path);
+        println!("  已翻译 {} 段, 失败 {} 段, 已用tokens: {}", self.units.len(), self.failed.len(), self.total_tokens);
+
+        let repaired: Vec<&str> = self.units.iter()
+            .filter(|(_, record)| record.repair.is_some())
+            .map(|(id, _)| id.as_str())
+            .collect();
+        if !repaired.is_empty() {
+            println!("  标记不一致已修复的段落: {}", repaired.join(", "));
+        }
+        for (id, error) in &self.failed {
+            println!("  段落 {} 失败: {}", id, error);
+        }
+    }
+}
+
+fn hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
+
+// 一批段落的译文, 与发送的段落一一对应
+struct BatchResult {
+    translations: Vec<String>,
+    total_tokens: u64,
+    repair: Option<Repair>,
+}
+
+struct BatchTranslator<'a> {
+    job: &'a Job,
+    api_config: &'a APIConfig,
+    output_key: &'a str,
+    retries: usize,
+    context: Option<&'a TranslationContext>,
+}
+
+impl BatchTranslator<'_> {
+    // 译文中的标记与发送的不一致时重新请求, 仍不一致则对半切分, 最后逐段翻译
+    async fn translate(&self, units: &[&Unit]) -> Result<BatchResult, String> {
+        let mut usage = Usage::default();
+        for attempt in 0..=self.retries {
+            if let Some(translations) = self.request_part(units, &mut usage).await? {
+                let repair = (attempt > 0).then_some(Repair::Retried);
+                return Ok(BatchResult { translations, total_tokens: usage.total_tokens, repair });
+            }
+        }
+
+        let (translations, per_unit) = translate_split(self, units, &mut usage).await?;
+        let repair = Some(if per_unit { Repair::PerUnit } else { Repair::Split });
+        Ok(BatchResult { translations, total_tokens: usage.total_tokens, repair })
+    }
+
+    async fn request(&self, text: &str, usage: &mut Usage) -> Result<String, String> {
+        let result = process_task(&self.job.config_data, self.api_config, &self.job.term, text.to_string(), self.context).await;
+        let (translation, result) = extract_translation(result, self.output_key)?;
+        usage.total_tokens += result.total_tokens;
+        usage.elapsed_time += result.elapsed_time;
+        Ok(translation)
+    }
+}
+
+impl SplitRequest for BatchTranslator<'_> {
+    type Item = Unit;
+
+    async fn request_part(&self, units: &[&Unit], usage: &mut Usage) -> Result<Option<Vec<String>>, String> {
+        let response = self.request(&batch_text(units), usage).await?;
+        Ok(parse_batch(&response, units).ok())
+    }
+
+    // 单独一段时不再要求标记, 占位符仍不一致时这一段失败
+    async fn request_single(&self, unit: &Unit, usage: &mut Usage) -> Result<String, String> {
+        let mut error = String::new();
+        for _ in 0..=self.retries {
+            let response = self.request(&unit.text, usage).await?;
+            let translation = parse_batch(&response, &[unit])
+                .map(|mut translations| translations.remove(0))
+                .unwrap_or_else(|_| response.trim().to_string());
+            match check_placeholders(&translation, unit.tags.len()) {
+                Ok(()) => return Ok(translation),
+                Err(e) => error = format!("#{}: {}", unit.id, e),
+            }
+        }
+        Err(error)
+    }
+}
+
+// 每段前加一行标记, 工作流需要原样保留这些标记
+fn batch_text(units: &[&Unit]) -> String {
+    units.iter()
+        .map(|unit| format!("[#{}]\n{}", unit.id, unit.text))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// 按标记拆分译文, 标记缺少、重复或多出时返回错误
+fn parse_batch(response: &str, units: &[&Unit]) -> Result<Vec<String>, String> {
+    let mut parts: HashMap<String, Vec<&str>> = HashMap::new();
+    let mut current: Option<String> = None;
+    for line in response.lines() {
+        if let Some(captures) = MARKER.captures(line) {
+            let id = captures[1].to_string();
+            if parts.contains_key(&id) {
+                return Err(format!("重复的标记 #{}", id));
+            }
+            let first = captures.get(2).map_or("", |text| text.as_str());
+            parts.insert(id.clone(), if first.is_empty() { Vec::new() } else { vec![first] });
+            current = Some(id);
+        } else if let Some(id) = &current {
+            parts.get_mut(id).unwrap().push(line);
+        } else if !line.trim().is_empty() {
+            return Err("译文开头缺少标记".to_string());
+        }
+    }
+
+    if let Some(extra) = parts.keys().find(|id| !units.iter().any(|unit| &unit.id == *id)) {
+        return Err(format!("多出的标记 #{}", extra));
+    }
+    units.iter()
+        .map(|unit| {
+            let lines = parts.get(&unit.id).ok_or_else(|| format!("缺少标记 #{}", unit.id))?;
+            let translation = lines.join("\n").trim_matches(['\n', '\r']).trim_end().to_string();
+            check_placeholders(&translation, unit.tags.len()).map_err(|e| format!("#{}: {}", unit.id, e))?;
+            Ok(translation)
+        })
+        .collect()
+}
+
+// 批次中各段的说明, 按 [#编号] 说明 的格式作为上文原文发送, 上文译文为空
+fn batch_context(document: &dyn Document, batch: &[Unit]) -> Option<TranslationContext> {
+    let notes: Vec<String> = batch.iter()
+        .filter_map(|unit| document.note(&unit.id).map(|note| format!("[#{}] {}", unit.id, note)))
+        .collect();
+    (!notes.is_empty()).then(|| TranslationContext { source: notes.join("\n"), translation: String::new() })
+}
+
+// lines 时每批固定段数, 其他方式按 tokens 预算装入, 每批至少一段
+fn batches(units: Vec<Unit>, strategy: &ChunkStrategy) -> Vec<Vec<Unit>> {
+    let mut batches: Vec<Vec<Unit>> = Vec::new();
+    let mut used = 0;
+    for unit in units {
+        let fits = match (strategy, batches.last()) {
+            (_, None) => false,
+            (ChunkStrategy::Lines(count), Some(batch)) => batch.len() < *count,
+            (ChunkStrategy::Packed { budget, tokenizer, .. }, Some(_)) => {
+                used + tokenizer.count(&unit.text) <= *budget
+            }
+        };
+        let tokens = match strategy {
+            ChunkStrategy::Packed { tokenizer, .. } => tokenizer.count(&unit.text),
+            ChunkStrategy::Lines(_) => 0,
+        };
+        if fits {
+            used += tokens;
+            batches.last_mut().unwrap().push(unit);
+        } else {
+            used = tokens;
+            batches.push(vec![unit]);
+        }
+    }
+    batches
+}
+
+// 字幕等结构化文件整体解析后按段翻译, 每批完成后保存进度, 全部结束后重新生成输出文件
+pub async fn translate_documents(
+    jobs: Vec<Job>,
+    api_config: Arc<APIConfig>,
+    options: &PipelineOptions,
+    shutdown: &Arc<Shutdown>
+) -> Result<RunSummary, String> {
+    let mut reports = Vec::new();
+    for job in jobs {
+        if shutdown.is_stopping() {
+            break;
+        }
+        reports.push(translate_document(Arc::new(job), &api_config, options, shutdown).await?);
+    }
+
+    let interrupted = shutdown.is_stopping();
+    if interrupted {
+        println!("翻译已中断, 进度已保存, 再次运行即可继续");
+    }
+    Ok(RunSummary { reports, interrupted })
+}
+
+async fn translate_document(
+    job: Arc<Job>,
+    api_config: &Arc<APIConfig>,
+    options: &PipelineOptions,
+    shutdown: &Arc<Shutdown>
+) -> Result<JobReport, String> {
+    let started = Instant::now();
+    let bytes = fs::read(&job.source_path).map_err(|e| format!("无法读取文件 {}: {}", job.source_path, e))?;
+    let (document, source): (Box<dyn Document>, String) = match job.format {
+        // EPUB 中的文件都是 UTF-8, 不按源文件编码解码
+        Format::Epub => {
+            let epub = epub::parse(&bytes, &job.config_data.target_lang).map_err(|e| format!("{}: {}", job.source_path, e))?;
+            (Box::new(epub), String::new())
+        }
+        format => {
+            let source = decode(job.source_encoding, &bytes);
+            let source = source.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(source);
+            let document = format.parse(&source, &job.config_data.target_lang, &options.documents).map_err(|e| format!("{}: {}", job.source_path, e))?;
+            (document, source)
+        }
+    };
+    let units = document.units();
+
+    let mut state = DocumentState::load(&job)?;
+    state.failed.clear();
+    let translated = state.translations(&units);
+    let pending: Vec<Unit> = units.iter()
+        .filter(|unit| !unit.is_blank() && !translated.contains_key(&unit.id))
+        .map(|unit| Unit { id: unit.id.clone(), text: unit.text.clone(), tags: unit.tags.clone() })
+        .collect();
+
+    let strategy = options.chunking.strategy_for(&job.term).map_err(|e| format!("{}: {}", job.name, e))?;
+    let pending_count = pending.len();
+    let batches = batches(pending, &strategy);
+    println!("{} 共 {} 段, 需要翻译 {} 段, 分为 {} 批", job.name, units.len(), pending_count, batches.len());
+
+    let retries = options.alignment.map_or(DEFAULT_ALIGN_RETRIES, |alignment| alignment.retries);
+    let output_key = Arc::new(options.output_key.clone());
+    let semaphore = Arc::new(Semaphore::new(options.task_num.max(1)));
+    let mut tasks = JoinSet::new();
+    let mut aborts = Vec::new();
+    for (index, batch) in batches.into_iter().enumerate() {
+        let context = batch_context(document.as_ref(), &batch);
+        let job = Arc::clone(&job);
+        let api_config = Arc::clone(api_config);
+        let output_key = Arc::clone(&output_key);
+        let semaphore = Arc::clone(&semaphore);
+        let shutdown = Arc::clone(shutdown);
+
+        aborts.push(tasks.spawn(async move {
+            // 中断时信号量关闭, 尚未发送的批次不再发送
+            let Ok(_permit) = semaphore.acquire().await else {
+                return (index, batch, None);
+            };
+            if shutdown.is_stopping() {
+                return (index, batch, None);
+            }
+            let translator = BatchTranslator { job: &job, api_config: &api_config, output_key: &output_key, retries, context: context.as_ref() };
+            let units: Vec<&Unit> = batch.iter().collect();
+            let result = translator.translate(&units).await;
+            (index, batch, Some(result))
+        }));
+    }
+    let grace = shutdown::spawn_grace_timer(shutdown, &semaphore, aborts, options.shutdown_grace_secs, "批次", |message| println!("{}", message));
+
+    let (mut done, mut failed, mut total_tokens) = (0, 0, 0);
+    while let Some(joined) = tasks.join_next().await {
+        let Ok((index, batch, Some(result))) = joined else {
+            continue;
+        };
+        match result {
+            Ok(result) => {
+                for (unit, translation) in batch.iter().zip(result.translations) {
+                    state.units.insert(unit.id.clone(), UnitRecord {
+                        hash: hash(&unit.source()),
+                        translation: document.escape(&translation),
+                        repair: result.repair,
+                    });
+                }
+                if let Some(repair) = result.repair {
+                    println!("{} 第 {} 批标记不一致, 已通过{}修复", job.name, index + 1, repair.label());
+                }
+                state.total_tokens += result.total_tokens;
+                total_tokens += result.total_tokens;
+                done += 1;
+                println!("{} 第 {} 批已返回结果", job.name, index + 1);
+            }
+            Err(e) => {
+                for unit in &batch {
+                    state.failed.insert(unit.id.clone(), e.clone());
+                }
+                failed += 1;
+                println!("{} 第 {} 批未返回结果: {}", job.name, index + 1, e);
+            }
+        }
+        state.save(&job).await?;
+    }
+    grace.abort();
+
+    let translations = document.restore_tags(&state.translations(&units));
+    write_document(&job, document.as_ref(), &translations, &source, options).await?;
+    state.output_path = job.output_path();
+    state.save(&job).await?;
+
+    let untranslated = units.iter().filter(|unit| !unit.is_blank() && !translations.contains_key(&unit.id)).count();
+
+    Ok(JobReport {
+        name: job.name.clone(),
+        source_lang: job.config_data.source_lang.clone(),
+        target_lang: job.config_data.target_lang.clone(),
+        output: job.output_path(),
+        chunks_done: done,
+        chunks_failed: failed,
+        total_tokens,
+        elapsed_secs: started.elapsed().as_secs_f64(),
+        complete: untranslated == 0,
+    })
+}
+
+// 译文中没有的段落保留原文, 换行符与纯文本相同按 line_endings 处理, 压缩包格式直接写入
+async fn write_document(
+    job: &Job,
+    document: &dyn Document,
+    translations: &HashMap<String, String>,
+    source: &str,
+    options: &PipelineOptions
+) -> Result<(), String> {
+    let bytes = match document.package(translations) {
+        Some(bytes) => bytes.map_err(|e| format!("无法生成输出文件 {}: {}", job.output_path(), e))?,
+        None => {
+            let rendered = document.render(translations);
+            let text = options.line_endings.format(rendered.strip_suffix('\n').unwrap_or(&rendered), source);
+            let (bytes, unmappable) = job.output_encoding.encode(&text, true);
+            if unmappable {
+                println!("{} 中有无法用 {} 表示的字符, 已替换为数字字符引用", job.name, job.output_encoding.encoding.name());
+            }
+            bytes
+        }
+    };
+    write_bytes_overwrite(&job.output_dir, &job.output_file_name, &bytes)
+        .await
+        .map_err(|e| format!("无法写入输出文件 {}: {}", job.output_path(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 各格式的示例, 包含该格式中需要原样写回的标签和结构
+    const FIXTURES: &[(Format, &str)] = &[
+        (Format::Srt, "1\n00:00:01,000 --> 00:00:02,000\n你好\n\n2\n00:00:03,000 --> 00:00:04,500\n第一行\n第二行\n"),
+        (Format::Vtt, "WEBVTT - 示例\n\nNOTE 注释\n\nintro\n00:00:01.000 --> 00:00:02.000 align:start\n<v Roger>你好 &amp; <i>再见</i>\n"),
+        (Format::Ass, "[Script Info]\nTitle: 示例\n\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nDialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{\\i1}你好,{\\i0}世界\\N第二行\n"),
+        (Format::Markdown, "---\ntitle: 标题\n---\n\n# 第一章\n\n这是**重要**的一段，包含 `code` 和[链接](https://example.com \"说明\")。\n\n```rust\nfn main() {}\n```\n"),
+        (Format::Html, "<!DOCTYPE html>\n<html><body>\n<p>第一段 <b>加粗</b> &amp; <code>x &lt; y</code>。</p>\n<img src=\"a.png\" alt=\"图片说明\" title='提示'>\n</body></html>\n"),
+    ];
+
+    // 模型原样返回时写入的译文, 与 translate_document 中的处理相同
+    fn echo_translations(document: &dyn Document) -> HashMap<String, String> {
+        let translations = document.units()
+            .into_iter()
+            .map(|unit| {
+                let translation = document.escape(&unit.text);
+                (unit.id, translation)
+            })
+            .collect();
+        document.restore_tags(&translations)
+    }
+
+    #[test]
+    fn round_trip_with_identity_translation() {
+        let options = DocumentOptions { front_matter: vec!["title".to_string()], ..DocumentOptions::default() };
+        for (format, source) in FIXTURES {
+            let document = format.parse(source, "zh", &options).unwrap();
+            assert!(!document.units().is_empty(), "{}", format.extension());
+            assert_eq!(document.render(&echo_translations(document.as_ref())), *source, "{}", format.extension());
+            assert_eq!(document.render(&HashMap::new()), *source, "{}", format.extension());
+        }
+    }
+
+    fn unit(id: &str, text: &str, tags: usize) -> Unit {
+        Unit { id: id.to_string(), text: text.to_string(), tags: (0..tags).map(|i| format!("<{}>", i)).collect() }
+    }
+
+    #[test]
+    fn parse_batch_accepts_reordered_markers() {
+        let units = [unit("1", "a", 0), unit("2", "b", 0)];
+        let units: Vec<&Unit> = units.iter().collect();
+        let translations = parse_batch("[#2]\nB\n[#1] A\nA2\n", &units).unwrap();
+        assert_eq!(translations, vec!["A\nA2", "B"]);
+    }
+
+    #[test]
+    fn parse_batch_rejects_missing_extra_and_duplicate_markers() {
+        let units = [unit("1", "a", 0), unit("2", "b", 0)];
+        let units: Vec<&Unit> = units.iter().collect();
+        assert_eq!(parse_batch("[#1]\nA\n", &units), Err("缺少标记 #2".to_string()));
+        assert_eq!(parse_batch("[#1]\nA\n[#2]\nB\n[#3]\nC", &units), Err("多出的标记 #3".to_string()));
+        assert_eq!(parse_batch("[#1]\nA\n[#1]\nB", &units), Err("重复的标记 #1".to_string()));
+        assert_eq!(parse_batch("A\n[#1]\nA\n[#2]\nB", &units), Err("译文开头缺少标记".to_string()));
+    }
+
+    #[test]
+    fn parse_batch_checks_placeholders() {
+        let units = [unit("1", "[[0]]a[[1]]", 2)];
+        let units: Vec<&Unit> = units.iter().collect();
+        assert_eq!(parse_batch("[#1]\n[[1]]A[[0]]", &units), Ok(vec!["[[1]]A[[0]]".to_string()]));
+        assert!(parse_batch("[#1]\n[[0]]A", &units).is_err());
+    }
+
+    #[test]
+    fn check_placeholders_rejects_missing_extra_and_repeated() {
+        assert_eq!(check_placeholders("[[1]] b [[0]]", 2), Ok(()));
+        assert_eq!(check_placeholders("[[0]] b", 2), Err("占位符 [[1]] 出现了 0 次".to_string()));
+        assert_eq!(check_placeholders("[[0]] [[1]] [[2]]", 2), Err("多出的占位符 [[2]]".to_string()));
+        assert_eq!(check_placeholders("[[0]] [[0]] [[1]]", 2), Err("占位符 [[0]] 出现了 2 次".to_string()));
+    }
+
+    #[test]
+    fn unmask_restores_tags_in_translated_order() {
+        let (masked, tags) = mask("<b>a</b> c", &Regex::new(r"</?b>").unwrap());
+        assert_eq!(masked, "[[0]]a[[1]] c");
+        assert_eq!(unmask("C [[0]]A[[1]]", &tags), "C <b>A</b>");
+    }
+}
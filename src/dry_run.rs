@@ -0,0 +1,223 @@
+use crate::chunking::{line_range, ChunkingOptions};
+use crate::document::Format;
+use crate::file_operations::{write_json_overwrite, write_txt_overwrite, LazyFileReader};
+use crate::pipeline::Job;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub const DEFAULT_PROMPT_TOKENS: usize = 200;
+
+// prompt_tokens 是工作流自身提示词的 token 数, 每次请求都会计入
+// 价格按每百万 tokens 计算, 输入和输出使用同一价格
+#[derive(Deserialize, Clone)]
+pub struct EstimateSettings {
+    #[serde(default = "default_prompt_tokens")]
+    pub prompt_tokens: usize,
+    #[serde(default)]
+    pub price_per_million_tokens: Option<f64>,
+}
+
+impl Default for EstimateSettings {
+    fn default() -> Self {
+        EstimateSettings {
+            prompt_tokens: DEFAULT_PROMPT_TOKENS,
+            price_per_million_tokens: None,
+        }
+    }
+}
+
+fn default_prompt_tokens() -> usize {
+    DEFAULT_PROMPT_TOKENS
+}
+
+pub struct DryRunOptions {
+    pub chunking: ChunkingOptions,
+    pub price_per_million_tokens: Option<f64>,
+    pub export: Option<String>,
+}
+
+// 行号从1开始, 包含 end_line
+#[derive(Serialize)]
+struct ChunkPreview {
+    job: String,
+    source_lang: String,
+    target_lang: String,
+    index: usize,
+    start_line: usize,
+    end_line: usize,
+    start_byte: u64,
+    end_byte: u64,
+    chars: usize,
+    source_tokens: usize,
+    prompt_tokens: usize,
+    output_tokens: usize,
+}
+
+#[derive(Serialize)]
+struct DryRunReport {
+    requests: usize,
+    prompt_tokens: usize,
+    output_tokens: usize,
+    estimated_cost: Option<f64>,
+    chunks: Vec<ChunkPreview>,
+}
+
+// 命令行中的 --dry-run 相关参数, 覆盖项目清单中的 estimate 设置
+pub struct DryRunFlags {
+    pub price: Option<f64>,
+    pub prompt_tokens: Option<usize>,
+    pub export: Option<String>,
+}
+
+impl DryRunFlags {
+    pub fn prompt_tokens(&self, estimate: &EstimateSettings) -> usize {
+        self.prompt_tokens.unwrap_or(estimate.prompt_tokens)
+    }
+
+    // chunking 中的 prompt_tokens 应当已经由 prompt_tokens() 得到
+    pub fn into_options(self, chunking: ChunkingOptions, estimate: &EstimateSettings) -> DryRunOptions {
+        DryRunOptions {
+            chunking,
+            price_per_million_tokens: self.price.or(estimate.price_per_million_tokens),
+            export: self.export,
+        }
+    }
+}
+
+// 按与正式翻译相同的方式切分源文件, 从上次记录的位置开始, 不调用工作流
+pub async fn dry_run(jobs: &[Job], options: &DryRunOptions) -> Result<(), String> {
+    let mut chunks = Vec::new();
+
+    for job in jobs {
+        if job.format != Format::Text {
+            println!("跳过 {}: 试运行只支持纯文本文件", job.source_path);
+            continue;
+        }
+        let mut state = job.load_state()?;
+        state.discard_in_flight();
+        // 旧版本迁移来的记录没有字节位置, 与正式翻译一样先按行号补全
+        state.fill_byte_offsets(&job.source_path)
+            .map_err(|e| format!("无法读取源文件 {}: {}", job.source_path, e))?;
+        let tokenizer = options.chunking.tokenizer.as_ref();
+        let glossary_tokens = tokenizer.count(&job.term);
+        let first_index = state.next_index();
+        let strategy = options.chunking.strategy_for(&job.term).map_err(|e| format!("{}: {}", job.name, e))?;
+
+        let mut reader = LazyFileReader::new(&job.source_path, job.source_encoding, strategy, state.next_byte(), state.next_line(), false, options.chunking.chapters.clone())
+            .await
+            .map_err(|e| format!("无法打开文件 {}: {}", job.source_path, e))?;
+
+        let job_start = chunks.len();
+        while let Some(chunk) = reader.read_next_chunk().await.map_err(|e| format!("读取文件 {} 出错: {}", job.source_path, e))? {
+            let source_tokens = tokenizer.count(&chunk.text);
+            chunks.push(ChunkPreview {
+                job: job.name.clone(),
+                source_lang: job.config_data.source_lang.clone(),
+                target_lang: job.config_data.target_lang.clone(),
+                index: first_index + reader.get_call_count() - 1,
+                start_line: chunk.start_line + 1,
+                end_line: chunk.end_line,
+                start_byte: chunk.start_byte,
+                end_byte: chunk.end_byte,
+                chars: chunk.text.chars().count(),
+                source_tokens,
+                prompt_tokens: source_tokens + glossary_tokens + options.chunking.prompt_tokens,
+                output_tokens: source_tokens,
+            });
+        }
+
+        println!(
+            "{} ({}→{}): 从第 {} 行开始, 共 {} 块, 术语表约 {} tokens",
+            job.name,
+            job.config_data.source_lang,
+            job.config_data.target_lang,
+            state.next_line() + 1,
+            chunks.len() - job_start,
+            glossary_tokens
+        );
+        if options.export.is_none() {
+            print_chunks(&chunks[job_start..]);
+        }
+    }
+
+    let prompt_tokens: usize = chunks.iter().map(|chunk| chunk.prompt_tokens).sum();
+    let output_tokens: usize = chunks.iter().map(|chunk| chunk.output_tokens).sum();
+    let estimated_cost = options.price_per_million_tokens
+        .map(|price| (prompt_tokens + output_tokens) as f64 / 1_000_000.0 * price);
+
+    println!(
+        "\n合计: {} 次请求, 输入约 {} tokens, 输出约 {} tokens",
+        chunks.len(),
+        prompt_tokens,
+        output_tokens
+    );
+    match estimated_cost {
+        Some(cost) => println!("预计费用: {:.4}", cost),
+        None => println!("未设置价格, 可以使用 --price=每百万tokens的价格 估算费用"),
+    }
+
+    if let Some(export) = &options.export {
+        let report = DryRunReport {
+            requests: chunks.len(),
+            prompt_tokens,
+            output_tokens,
+            estimated_cost,
+            chunks,
+        };
+        export_report(export, &report).await?;
+        println!("已导出到 {}", export);
+    }
+    Ok(())
+}
+
+fn print_chunks(chunks: &[ChunkPreview]) {
+    println!("  {:>6} {:>15} {:>21} {:>8} {:>8} {:>8}", "chunk", "行", "字节", "字数", "原文", "请求");
+    for chunk in chunks {
+        println!(
+            "  {:>6} {:>15} {:>21} {:>8} {:>8} {:>8}",
+            chunk.index,
+            line_range(chunk.start_line - 1, chunk.end_line),
+            format!("{}-{}", chunk.start_byte, chunk.end_byte),
+            chunk.chars,
+            chunk.source_tokens,
+            chunk.prompt_tokens
+        );
+    }
+}
+
+// 根据扩展名导出为 csv 或 json
+async fn export_report(export: &str, report: &DryRunReport) -> Result<(), String> {
+    let path = Path::new(export);
+    let folder = path.parent().map(|parent| parent.to_string_lossy().to_string()).unwrap_or_default();
+    let filename = path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| format!("无效的导出路径: {}", export))?;
+
+    let result = if path.extension().is_some_and(|extension| extension == "csv") {
+        let mut csv = String::from(
+            "job,source_lang,target_lang,index,start_line,end_line,start_byte,end_byte,chars,source_tokens,prompt_tokens,output_tokens\n"
+        );
+        for c in &report.chunks {
+            csv.push_str(&format!(
+                "\"{}\",{},{},{},{},{},{},{},{},{},{},{}\n",
+                c.job.replace('"', "\"\""),
+                c.source_lang,
+                c.target_lang,
+                c.index,
+                c.start_line,
+                c.end_line,
+                c.start_byte,
+                c.end_byte,
+                c.chars,
+                c.source_tokens,
+                c.prompt_tokens,
+                c.output_tokens
+            ));
+        }
+        write_txt_overwrite(&folder, &filename, &csv).await
+    } else {
+        write_json_overwrite(&folder, &filename, report).await
+    };
+
+    result.map_err(|e| format!("无法导出到 {}: {}", export, e))
+}
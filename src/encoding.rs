@@ -0,0 +1,231 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use std::fs::File;
+use std::io::{self, Error, ErrorKind, Read};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+// 自动检测时读取的源文件开头的字节数
+const DETECT_BYTES: u64 = 64 * 1024;
+
+// 源文件编码可以指定, 未指定时自动检测; 输出默认为不带 BOM 的 UTF-8
+#[derive(Clone, Copy, Default)]
+pub struct EncodingOptions {
+    pub source: Option<&'static Encoding>,
+    pub output: OutputEncoding,
+}
+
+impl EncodingOptions {
+    pub fn parse(source: Option<&str>, output: Option<&str>, bom: bool) -> Result<Self, String> {
+        Ok(EncodingOptions {
+            source: source.map(encoding_from_label).transpose()?,
+            output: OutputEncoding {
+                encoding: output.map(encoding_from_label).transpose()?.unwrap_or(UTF_8),
+                bom,
+            },
+        })
+    }
+
+    pub fn source_encoding(&self, path: &str) -> &'static Encoding {
+        self.source.unwrap_or_else(|| detect_encoding(path))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct OutputEncoding {
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+}
+
+impl Default for OutputEncoding {
+    fn default() -> Self {
+        OutputEncoding { encoding: UTF_8, bom: false }
+    }
+}
+
+impl OutputEncoding {
+    // 记录在任务状态中, 继续翻译时检查是否与已写入的内容一致
+    pub fn label(&self) -> String {
+        if self.bom {
+            format!("{} BOM", self.encoding.name())
+        } else {
+            self.encoding.name().to_string()
+        }
+    }
+
+    // at_start 为 true 时写在文件开头, 需要时加上 BOM
+    // 返回的 bool 表示是否有无法用该编码表示的字符, 这些字符会被替换为 &#...; 形式的数字字符引用
+    pub fn encode(&self, text: &str, at_start: bool) -> (Vec<u8>, bool) {
+        let mut bytes = Vec::with_capacity(text.len() + 3);
+        if at_start && self.bom {
+            bytes.extend_from_slice(bom(self.encoding));
+        }
+
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            // encoding_rs 不支持编码为 UTF-16
+            for unit in text.encode_utf16() {
+                let pair = if self.encoding == UTF_16LE { unit.to_le_bytes() } else { unit.to_be_bytes() };
+                bytes.extend_from_slice(&pair);
+            }
+            return (bytes, false);
+        }
+
+        let (encoded, _, unmappable) = self.encoding.encode(text);
+        bytes.extend_from_slice(&encoded);
+        (bytes, unmappable)
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        self.encoding.decode_with_bom_removal(bytes).0.into_owned()
+    }
+}
+
+fn bom(encoding: &'static Encoding) -> &'static [u8] {
+    if encoding == UTF_16LE {
+        b"\xFF\xFE"
+    } else if encoding == UTF_16BE {
+        b"\xFE\xFF"
+    } else if encoding == UTF_8 {
+        b"\xEF\xBB\xBF"
+    } else {
+        b""
+    }
+}
+
+// 只支持能按行读取的编码: 兼容 ASCII 的编码和 UTF-16, 不支持 ISO-2022-JP 这类有状态的编码
+pub fn is_supported(encoding: &'static Encoding) -> bool {
+    encoding.is_ascii_compatible() || encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+pub fn encoding_from_label(label: &str) -> Result<&'static Encoding, String> {
+    let encoding = Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("无效的编码: {}", label))?;
+    if !is_supported(encoding) {
+        return Err(format!("不支持 {} 编码", encoding.name()));
+    }
+    Ok(encoding)
+}
+
+// 有 BOM 时按 BOM 确定, 开头的内容是合法的 UTF-8 时为 UTF-8, 否则猜测
+// 无法读取时按 UTF-8 处理, 之后打开文件时再报告错误
+pub fn detect_encoding(path: &str) -> &'static Encoding {
+    let mut sample = Vec::new();
+    if let Ok(file) = File::open(path) {
+        let _ = file.take(DETECT_BYTES).read_to_end(&mut sample);
+    }
+
+    if let Some((encoding, _)) = Encoding::for_bom(&sample) {
+        return encoding;
+    }
+    // 样本末尾可能截断了一个多字节字符
+    match std::str::from_utf8(&sample) {
+        Ok(_) => return UTF_8,
+        Err(e) if e.error_len().is_none() => return UTF_8,
+        Err(_) => {}
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(&sample, (sample.len() as u64) < DETECT_BYTES);
+    detector.guess(None, true)
+}
+
+// 文件开头 BOM 的长度, 读取时跳过
+pub fn bom_len(encoding: &'static Encoding, head: &[u8]) -> usize {
+    match Encoding::for_bom(head) {
+        Some((bom_encoding, len)) if bom_encoding == encoding => len,
+        _ => 0,
+    }
+}
+
+pub fn unsupported_error(encoding: &'static Encoding) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("不支持 {} 编码", encoding.name()))
+}
+
+// 读取一行原始字节, 包括换行符, 返回读取的字节数
+// 兼容 ASCII 的编码中多字节字符不会包含 0x0A, UTF-16 的换行符是 0x0A 和 0x00 组成的码元
+pub async fn read_raw_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    encoding: &'static Encoding,
+    buf: &mut Vec<u8>
+) -> io::Result<usize> {
+    if encoding.is_ascii_compatible() {
+        return reader.read_until(b'\n', buf).await;
+    }
+
+    let start = buf.len();
+    while reader.read_until(b'\n', buf).await? > 0 && buf.last() == Some(&b'\n') {
+        let len = buf.len() - start;
+        if encoding == UTF_16BE {
+            if len.is_multiple_of(2) && buf[buf.len() - 2] == 0 {
+                break;
+            }
+        } else if !len.is_multiple_of(2) {
+            // 0x0A 是码元的低字节, 再读入高字节
+            let mut high = [0u8; 1];
+            if reader.read(&mut high).await? == 0 {
+                break;
+            }
+            buf.push(high[0]);
+            if high[0] == 0 {
+                break;
+            }
+        }
+    }
+    Ok(buf.len() - start)
+}
+
+pub fn decode(encoding: &'static Encoding, bytes: &[u8]) -> String {
+    encoding.decode_without_bom_handling(bytes).0.into_owned()
+}
+
+// 解码后的一段文本在源文件中占的字节数, 用于在行内切分时计算字节位置
+pub fn source_len(encoding: &'static Encoding, text: &str) -> usize {
+    if encoding == UTF_8 {
+        text.len()
+    } else if encoding == UTF_16LE || encoding == UTF_16BE {
+        text.encode_utf16().count() * 2
+    } else {
+        encoding.encode(text).0.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    fn utf16(text: &str, encoding: &'static Encoding) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| if encoding == UTF_16BE { unit.to_be_bytes() } else { unit.to_le_bytes() })
+            .collect()
+    }
+
+    async fn read_lines(bytes: &[u8], encoding: &'static Encoding) -> Vec<String> {
+        // 缓冲区很小, 码元的两个字节也会分在两次读取中
+        let mut reader = BufReader::with_capacity(3, bytes);
+        let mut lines = Vec::new();
+        loop {
+            let mut buf = Vec::new();
+            if read_raw_line(&mut reader, encoding, &mut buf).await.unwrap() == 0 {
+                return lines;
+            }
+            lines.push(decode(encoding, &buf));
+        }
+    }
+
+    // 上 是 U+4E0A, ਊ 是 U+0A0A, U+0A00 的高字节和 U+4E0A 的低字节都是 0x0A
+    #[tokio::test]
+    async fn read_raw_line_splits_utf16_only_at_newline_code_units() {
+        let text = "上ਊ\u{0A00}一\n下\r\n末";
+        for encoding in [UTF_16LE, UTF_16BE] {
+            let lines = read_lines(&utf16(text, encoding), encoding).await;
+            assert_eq!(lines, vec!["上ਊ\u{0A00}一\n", "下\r\n", "末"], "{}", encoding.name());
+        }
+    }
+
+    #[tokio::test]
+    async fn read_raw_line_keeps_ascii_compatible_lines() {
+        let (bytes, _, _) = encoding_rs::SHIFT_JIS.encode("一行目\n二行目");
+        let lines = read_lines(&bytes, encoding_rs::SHIFT_JIS).await;
+        assert_eq!(lines, vec!["一行目\n", "二行目"]);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::document::{Document, Unit};
+use crate::html::{self, Html};
+use regex::Regex;
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Write};
+use std::sync::LazyLock;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+static LANGUAGE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(<dc:language(?:\s[^>]*)?>)[^<]*(</dc:language>)").unwrap()
+});
+
+// EPUB 电子书: 按书脊顺序翻译其中的 XHTML 文件和目录, 图片、样式表等其他文件原样复制
+pub struct Epub {
+    archive: Vec<u8>,
+    package_path: String,
+    package: String,
+    parts: Vec<Part>,
+}
+
+// 需要翻译的一个文件, 段落编号加上 prefix, 例如书脊中第 3 个文件的第 12 段是 3.12
+struct Part {
+    path: String,
+    prefix: String,
+    document: Html,
+}
+
+pub fn parse(bytes: &[u8], target_lang: &str) -> Result<Epub, String> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).map_err(|e| format!("不是有效的 EPUB 文件: {}", e))?;
+
+    let container = read_entry(&mut archive, "META-INF/container.xml")?;
+    let package_path = html::start_tags(&container).into_iter()
+        .find(|(name, _)| local_name(name) == "rootfile")
+        .and_then(|(_, attributes)| attributes.get("full-path").cloned())
+        .ok_or("container.xml 中没有 rootfile")?;
+    let package = read_entry(&mut archive, &package_path)?;
+
+    // 清单中的文件: id -> (路径, 媒体类型, properties)
+    let mut manifest: HashMap<String, (String, String, String)> = HashMap::new();
+    let mut spine = Vec::new();
+    let mut ncx = None;
+    for (name, attributes) in html::start_tags(&package) {
+        let attribute = |key: &str| attributes.get(key).cloned().unwrap_or_default();
+        match local_name(&name) {
+            "item" => {
+                let path = resolve(&package_path, &attribute("href"));
+                manifest.insert(attribute("id"), (path, attribute("media-type"), attribute("properties")));
+            }
+            "itemref" => spine.push(attribute("idref")),
+            "spine" => ncx = attributes.get("toc").cloned(),
+            _ => {}
+        }
+    }
+
+    let mut parts = Vec::new();
+    for (i, id) in spine.iter().enumerate() {
+        let (path, media_type, _) = manifest.get(id).ok_or_else(|| format!("书脊中的 {} 不在清单中", id))?;
+        if media_type == "application/xhtml+xml" || media_type == "text/html" {
+            parts.push(Part { path: path.clone(), prefix: (i + 1).to_string(), document: html::parse(&read_entry(&mut archive, path)?) });
+        }
+    }
+    // EPUB 3 的导航文件和 EPUB 2 的 NCX 目录, 导航文件在书脊中时已经翻译
+    let nav = manifest.values().find(|(_, _, properties)| properties.split_whitespace().any(|property| property == "nav"));
+    if let Some((path, _, _)) = nav.filter(|(path, _, _)| !parts.iter().any(|part| &part.path == path)) {
+        parts.push(Part { path: path.clone(), prefix: "nav".to_string(), document: html::parse(&read_entry(&mut archive, path)?) });
+    }
+    let ncx = ncx.and_then(|id| manifest.get(&id))
+        .or_else(|| manifest.values().find(|(_, media_type, _)| media_type == "application/x-dtbncx+xml"));
+    if let Some((path, _, _)) = ncx {
+        parts.push(Part { path: path.clone(), prefix: "toc".to_string(), document: html::parse(&read_entry(&mut archive, path)?) });
+    }
+    if parts.is_empty() {
+        return Err("书脊中没有 XHTML 文件".to_string());
+    }
+
+    Ok(Epub { archive: bytes.to_vec(), package: set_language(&package, target_lang), package_path, parts })
+}
+
+fn read_entry(archive: &mut ZipArchive<Cursor<&[u8]>>, path: &str) -> Result<String, String> {
+    let mut entry = archive.by_name(path).map_err(|e| format!("无法读取 {}: {}", path, e))?;
+    let mut bytes = Vec::new();
+    entry.read_to_end(&mut bytes).map_err(|e| format!("无法读取 {}: {}", path, e))?;
+    let text = String::from_utf8(bytes).map_err(|_| format!("{} 不是 UTF-8 编码", path))?;
+    Ok(text.strip_prefix('\u{feff}').map(str::to_string).unwrap_or(text))
+}
+
+// 去掉 opf: 等命名空间前缀
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+// 清单中的 href 相对于 OPF 文件所在目录, 可能经过百分号编码
+fn resolve(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or(href);
+    let mut segments: Vec<&str> = base.split('/').collect();
+    segments.pop();
+    for segment in href.split('/') {
+        match segment {
+            "." | "" => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    percent_decode(&segments.join("/"))
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = (bytes[i] == b'%' && i + 2 < bytes.len())
+            .then(|| std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+            .flatten();
+        match hex {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// 把 dc:language 改为目标语言, 没有时加在 metadata 的末尾
+fn set_language(package: &str, target_lang: &str) -> String {
+    if LANGUAGE.is_match(package) {
+        return LANGUAGE.replacen(package, 1, |captures: &regex::Captures| format!("{}{}{}", &captures[1], target_lang, &captures[2])).into_owned();
+    }
+    match package.find("</metadata>").or_else(|| package.find("</opf:metadata>")) {
+        Some(end) => format!("{}<dc:language>{}</dc:language>\n{}", &package[..end], target_lang, &package[end..]),
+        None => package.to_string(),
+    }
+}
+
+impl Epub {
+    // 按原来的顺序写入各文件, 未修改的文件直接复制压缩后的数据, mimetype 仍然是第一个且不压缩
+    fn write(&self, translations: &HashMap<String, String>) -> Result<Vec<u8>, String> {
+        let mut archive = ZipArchive::new(Cursor::new(self.archive.as_slice())).map_err(|e| e.to_string())?;
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        for i in 0..archive.len() {
+            let entry = archive.by_index_raw(i).map_err(|e| e.to_string())?;
+            let name = entry.name().to_string();
+            let content = if name == self.package_path {
+                Some(self.package.clone())
+            } else {
+                self.parts.iter().find(|part| part.path == name).map(|part| part.render(translations))
+            };
+            match content {
+                Some(content) => {
+                    drop(entry);
+                    writer.start_file(name.as_str(), options).map_err(|e| format!("无法写入 {}: {}", name, e))?;
+                    writer.write_all(content.as_bytes()).map_err(|e| format!("无法写入 {}: {}", name, e))?;
+                }
+                None => writer.raw_copy_file(entry).map_err(|e| format!("无法写入 {}: {}", name, e))?,
+            }
+        }
+        writer.finish().map(Cursor::into_inner).map_err(|e| e.to_string())
+    }
+}
+
+impl Part {
+    // 去掉编号前缀后属于这个文件的译文
+    fn own_translations(&self, translations: &HashMap<String, String>) -> HashMap<String, String> {
+        let prefix = format!("{}.", self.prefix);
+        translations.iter()
+            .filter_map(|(id, translation)| Some((id.strip_prefix(&prefix)?.to_string(), translation.clone())))
+            .collect()
+    }
+
+    fn render(&self, translations: &HashMap<String, String>) -> String {
+        self.document.render(&self.own_translations(translations))
+    }
+}
+
+impl Document for Epub {
+    fn units(&self) -> Vec<Unit> {
+        self.parts.iter()
+            .flat_map(|part| {
+                part.document.units().into_iter().map(|unit| Unit { id: format!("{}.{}", part.prefix, unit.id), ..unit })
+            })
+            .collect()
+    }
+
+    // 输出是压缩包, 由 package 生成
+    fn render(&self, _translations: &HashMap<String, String>) -> String {
+        String::new()
+    }
+
+    fn package(&self, translations: &HashMap<String, String>) -> Option<Result<Vec<u8>, String>> {
+        Some(self.write(translations))
+    }
+
+    fn escape(&self, translation: &str) -> String {
+        self.parts[0].document.escape(translation)
+    }
+
+    fn restore_tags(&self, translations: &HashMap<String, String>) -> HashMap<String, String> {
+        self.parts.iter()
+            .flat_map(|part| {
+                part.document.restore_tags(&part.own_translations(translations))
+                    .into_iter()
+                    .map(|(id, translation)| (format!("{}.{}", part.prefix, id), translation))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?><container><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#;
+    const PACKAGE: &str = r#"<package><metadata><dc:title>书</dc:title><dc:language>ja</dc:language></metadata><manifest><item id="c1" href="Text/chapter%201.xhtml" media-type="application/xhtml+xml"/><item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/><item id="css" href="style.css" media-type="text/css"/><item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/></manifest><spine toc="ncx"><itemref idref="c1"/></spine></package>"#;
+    const CHAPTER: &str = "<html><body><h1>第一章</h1><p>本文 <em>强调</em>。</p></body></html>";
+    const NAV: &str = "<html><body><nav><ol><li><a href=\"Text/chapter%201.xhtml\">第一章</a></li></ol></nav></body></html>";
+    const NCX: &str = "<?xml version=\"1.0\"?><ncx><navMap><navPoint id=\"p1\" playOrder=\"1\"><navLabel><text>第一章</text></navLabel><content src=\"Text/chapter%201.xhtml\"/></navPoint></navMap></ncx>";
+
+    fn book() -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        writer.start_file("mimetype", stored).unwrap();
+        writer.write_all(b"application/epub+zip").unwrap();
+        for (name, content) in [
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", PACKAGE),
+            ("OEBPS/Text/chapter 1.xhtml", CHAPTER),
+            ("OEBPS/nav.xhtml", NAV),
+            ("OEBPS/toc.ncx", NCX),
+            ("OEBPS/style.css", "p { margin: 0 }"),
+        ] {
+            writer.start_file(name, deflated).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn entries(bytes: &[u8]) -> Vec<(String, String)> {
+        let mut archive = ZipArchive::new(Cursor::new(bytes)).unwrap();
+        (0..archive.len())
+            .map(|i| {
+                let mut entry = archive.by_index(i).unwrap();
+                let mut content = String::new();
+                entry.read_to_string(&mut content).unwrap();
+                (entry.name().to_string(), content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn copies_other_entries_and_sets_language() {
+        let source = book();
+        let epub = parse(&source, "zh").unwrap();
+        let output = epub.package(&HashMap::new()).unwrap().unwrap();
+
+        let expected: Vec<(String, String)> = entries(&source).into_iter()
+            .map(|(name, content)| match name.as_str() {
+                "OEBPS/content.opf" => (name, content.replace("<dc:language>ja</dc:language>", "<dc:language>zh</dc:language>")),
+                _ => (name, content),
+            })
+            .collect();
+        assert_eq!(entries(&output), expected);
+
+        let mut archive = ZipArchive::new(Cursor::new(output.as_slice())).unwrap();
+        assert_eq!(archive.by_index(0).unwrap().compression(), CompressionMethod::Stored);
+    }
+
+    #[test]
+    fn prefixes_units_with_spine_position_and_nav() {
+        let epub = parse(&book(), "zh").unwrap();
+        let units: Vec<(String, String)> = epub.units().into_iter().map(|unit| (unit.id, unit.text)).collect();
+        assert!(units.iter().any(|(id, text)| id.starts_with("1.") && text == "第一章"));
+        assert!(units.iter().any(|(id, text)| id.starts_with("nav.") && text.contains("第一章")));
+
+        let translations: HashMap<String, String> = units.iter()
+            .filter(|(id, _)| id.starts_with("1."))
+            .map(|(id, text)| (id.clone(), text.replace("第一章", "Chapter 1")))
+            .collect();
+        let output = entries(&epub.package(&translations).unwrap().unwrap());
+        let chapter = output.iter().find(|(name, _)| name == "OEBPS/Text/chapter 1.xhtml").unwrap();
+        assert!(chapter.1.contains("<h1>Chapter 1</h1>"));
+        let nav = output.iter().find(|(name, _)| name == "OEBPS/nav.xhtml").unwrap();
+        assert_eq!(nav.1, NAV);
+    }
+
+    #[test]
+    fn translates_nav_and_ncx_labels() {
+        let epub = parse(&book(), "zh").unwrap();
+        let units = epub.units();
+        assert!(units.iter().any(|unit| unit.id.starts_with("toc.") && unit.text == "第一章"));
+
+        let translations: HashMap<String, String> = units.into_iter()
+            .map(|unit| (unit.id, unit.text.replace("第一章", "Chapter 1")))
+            .collect();
+        let output = entries(&epub.package(&epub.restore_tags(&translations)).unwrap().unwrap());
+        let ncx = output.iter().find(|(name, _)| name == "OEBPS/toc.ncx").unwrap();
+        assert!(ncx.1.contains("<navLabel><text>Chapter 1</text></navLabel>"), "{}", ncx.1);
+        assert!(ncx.1.contains("<content src=\"Text/chapter%201.xhtml\"/>"));
+        let nav = output.iter().find(|(name, _)| name == "OEBPS/nav.xhtml").unwrap();
+        assert!(nav.1.contains("<a href=\"Text/chapter%201.xhtml\">Chapter 1</a>"), "{}", nav.1);
+    }
+
+    #[test]
+    fn resolves_relative_and_encoded_paths() {
+        assert_eq!(resolve("OEBPS/content.opf", "Text/chapter%201.xhtml#top"), "OEBPS/Text/chapter 1.xhtml");
+        assert_eq!(resolve("OEBPS/content.opf", "../images/a.png"), "images/a.png");
+        assert_eq!(percent_decode("%E7%AB%A0%2"), "章%2");
+    }
+}
@@ -1,131 +1,463 @@
+use std::collections::VecDeque;
 use std::io::{self, Error, ErrorKind, Read};
 use std::path::Path;
+use std::sync::Arc;
 
+use crate::chapters::ChapterMatcher;
+use crate::chunking::{sentence_ends, split_point, ChunkMode, ChunkStrategy};
+use crate::encoding::{bom_len, decode, is_supported, read_raw_line, source_len, unsupported_error};
+use crate::tokenizer::Tokenizer;
+
+use encoding_rs::Encoding;
 use serde::Serialize;
-use serde_json;
-use serde_yaml;
+use sha2::{Digest, Sha256};
 use tokio::{self, fs};
 use tokio::fs::{File, OpenOptions};
-use tokio::io::{AsyncWriteExt, AsyncBufReadExt, BufReader};
-use toml;
+use tokio::io::{AsyncWriteExt, AsyncBufReadExt, AsyncReadExt, AsyncSeekExt, BufReader, SeekFrom};
 
 pub const CONFIG_DIR: &str = "config";
 pub const TRANSLATION_DIR: &str = "translation";
 pub const TERM_DIR: &str = "term";
+pub const REPORT_DIR: &str = "report";
+pub const STATE_DIR: &str = "state";
+
+// 行号从0开始, end_line 不包含在内, 被跳过的空白块计入下一块的范围
+// hash 是 start_byte..end_byte 之间原始字节的 SHA-256, raw 是这些字节解码后的原始文本
+// heading 为 true 时这一块只有章节标题
+pub struct Chunk {
+    pub text: String,
+    pub raw: String,
+    pub heading: bool,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub start_byte: u64,
+    pub end_byte: u64,
+    pub hash: String,
+}
 
 pub struct LazyFileReader {
     reader: BufReader<File>,
-    chunk_size: usize,
-    buffer: Vec<String>,
+    encoding: &'static Encoding,
+    strategy: ChunkStrategy,
+    pushback: VecDeque<Segment>,
     call_count: usize,
-    read_count: usize
+    line_number: usize,
+    byte_offset: u64,
+    keep_blank_tail: bool,
+    chapters: Option<Arc<ChapterMatcher>>,
+    line_open: bool
+}
+
+// 读取的最小单位, 通常是完整的一行; 单独一行超出预算时会被切成几段
+// raw 是解码后的文本, bytes 是对应的源文件字节
+struct Segment {
+    raw: String,
+    bytes: Vec<u8>,
+    ends_line: bool,
 }
 
 impl LazyFileReader {
-    pub async fn new(file_path: &str, chunk_size: usize, skip_lines: usize) -> io::Result<Self> {
-        let file = File::open(file_path).await?;
+    // 从 start_byte 处开始读取, start_line 是该位置对应的行号, 从文件开头读取时跳过 BOM
+    // keep_blank_tail 为 true 时文件末尾的空行作为一个不含内容的块返回, 否则丢弃
+    // chapters 不为空时在章节标题处切开
+    pub async fn new(
+        file_path: &str,
+        encoding: &'static Encoding,
+        strategy: ChunkStrategy,
+        start_byte: u64,
+        start_line: usize,
+        keep_blank_tail: bool,
+        chapters: Option<Arc<ChapterMatcher>>
+    ) -> io::Result<Self> {
+        if !is_supported(encoding) {
+            return Err(unsupported_error(encoding));
+        }
+
+        let mut file = File::open(file_path).await?;
+        file.seek(SeekFrom::Start(start_byte)).await?;
         let mut reader = BufReader::new(file);
 
-        let mut line = String::new();
-        for _ in 0..skip_lines {
-            reader.read_line(&mut line).await?;
-            line.clear();
+        let mut start_byte = start_byte;
+        if start_byte == 0 {
+            let skip = bom_len(encoding, reader.fill_buf().await?);
+            reader.consume(skip);
+            start_byte = skip as u64;
         }
 
         Ok(LazyFileReader {
             reader,
-            chunk_size,
-            buffer: Vec::new(),
+            encoding,
+            strategy,
+            pushback: VecDeque::new(),
             call_count: 0,
-            read_count: 0
+            line_number: start_line,
+            byte_offset: start_byte,
+            keep_blank_tail,
+            chapters,
+            line_open: false
         })
     }
 
-    pub async fn read_next_chunk(&mut self) -> io::Result<Option<String>> {
+    pub async fn read_next_chunk(&mut self) -> io::Result<Option<Chunk>> {
         self.call_count += 1;
+        let start_line = self.line_number;
+        let start_byte = self.byte_offset;
+        let mut hasher = Sha256::new();
+        let mut raw = String::new();
         loop {
-            self.read_count += 1;
-            self.buffer.clear();
-            let mut lines_read = 0;
+            let segments = match self.strategy.clone() {
+                ChunkStrategy::Lines(num_lines) => self.take_lines(num_lines).await?,
+                ChunkStrategy::Packed { mode: ChunkMode::Tokens | ChunkMode::Lines, budget, tokenizer } => {
+                    self.take_tokens(budget, tokenizer.as_ref()).await?
+                }
+                ChunkStrategy::Packed { mode, budget, tokenizer } => self.take_units(mode, budget, tokenizer.as_ref()).await?,
+            };
+
+            if segments.is_empty() {
+                if self.keep_blank_tail && !raw.is_empty() {
+                    return Ok(Some(Chunk {
+                        text: String::new(),
+                        raw,
+                        heading: false,
+                        start_line,
+                        end_line: self.line_number,
+                        start_byte,
+                        end_byte: self.byte_offset,
+                        hash: format!("{:x}", hasher.finalize()),
+                    }));
+                }
+                return Ok(None);
+            }
+            let (segments, heading) = self.cut_at_heading(segments);
+
+            // 同一行被切开的几段拼回一行, 行尾空白在整行读完后去掉
+            let mut lines: Vec<String> = Vec::new();
+            let mut open_line = false;
+            for segment in &segments {
+                hasher.update(&segment.bytes);
+                raw.push_str(&segment.raw);
+                self.byte_offset += segment.bytes.len() as u64;
+
+                let text = segment.raw.trim_end_matches(['\n', '\r']);
+                match lines.last_mut() {
+                    Some(line) if open_line => line.push_str(text),
+                    _ => lines.push(text.to_string()),
+                }
+                open_line = !segment.ends_line;
+                if segment.ends_line {
+                    self.line_number += 1;
+                    let line = lines.last_mut().unwrap();
+                    line.truncate(line.trim_end().len());
+                }
+            }
+            self.line_open = open_line;
+            // 块在行内结束时, 切分处的空格不属于这一块的译文
+            if open_line {
+                let line = lines.last_mut().unwrap();
+                line.truncate(line.trim_end().len());
+            }
 
-            while lines_read < self.chunk_size {
-                let mut line = String::new();
-                let bytes_read = self.reader.read_line(&mut line).await?;
+            if !lines.iter().all(|line| line.trim().is_empty()) {
+                return Ok(Some(Chunk {
+                    text: lines.join("\n"),
+                    raw,
+                    heading,
+                    start_line,
+                    end_line: self.line_number,
+                    start_byte,
+                    end_byte: self.byte_offset,
+                    hash: format!("{:x}", hasher.finalize()),
+                }));
+            }
+        }
+    }
 
-                if bytes_read == 0 {
-                    break;
+    // 章节标题之前有正文时在标题前切开, 标题之前只有空行时标题单独成块, 之后的部分放回
+    fn cut_at_heading(&mut self, mut segments: Vec<Segment>) -> (Vec<Segment>, bool) {
+        let Some(chapters) = &self.chapters else {
+            return (segments, false);
+        };
+
+        let mut starts_line = !self.line_open;
+        let mut seen_text = false;
+        for i in 0..segments.len() {
+            let segment = &segments[i];
+            if starts_line && segment.ends_line && chapters.is_heading(&segment.raw) {
+                let cut = if seen_text { i } else { i + 1 };
+                for segment in segments.split_off(cut).into_iter().rev() {
+                    self.pushback.push_front(segment);
                 }
+                return (segments, !seen_text);
+            }
+            seen_text |= !segment.raw.trim().is_empty();
+            starts_line = segment.ends_line;
+        }
+        (segments, false)
+    }
+
+    async fn next_segment(&mut self) -> io::Result<Option<Segment>> {
+        if let Some(segment) = self.pushback.pop_front() {
+            return Ok(Some(segment));
+        }
+
+        let mut bytes = Vec::new();
+        if read_raw_line(&mut self.reader, self.encoding, &mut bytes).await? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(Segment { raw: decode(self.encoding, &bytes), bytes, ends_line: true }))
+    }
+
+    // 在解码后文本的 at 处把一段切成两段, 前一段不再包含行尾
+    fn split_segment(&self, segment: Segment, at: usize) -> (Segment, Segment) {
+        let split_byte = source_len(self.encoding, &segment.raw[..at]).min(segment.bytes.len());
+        let head = Segment {
+            raw: segment.raw[..at].to_string(),
+            bytes: segment.bytes[..split_byte].to_vec(),
+            ends_line: false,
+        };
+        let tail = Segment {
+            raw: segment.raw[at..].to_string(),
+            bytes: segment.bytes[split_byte..].to_vec(),
+            ends_line: segment.ends_line,
+        };
+        (head, tail)
+    }
 
-                line = line.trim_end().to_string();
-                self.buffer.push(line);
-                lines_read += 1;
+    async fn take_lines(&mut self, num_lines: usize) -> io::Result<Vec<Segment>> {
+        let mut segments = Vec::new();
+        while segments.len() < num_lines {
+            match self.next_segment().await? {
+                Some(segment) => segments.push(segment),
+                None => break,
             }
+        }
+        Ok(segments)
+    }
 
-            if self.buffer.is_empty() {
-                return Err(Error::new(ErrorKind::UnexpectedEof, "文件已读取完毕"));
+    // 按行装入直到达到预算, 只有单独一行就超出预算时才在行内切分
+    async fn take_tokens(&mut self, budget: usize, tokenizer: &dyn Tokenizer) -> io::Result<Vec<Segment>> {
+        let mut segments = Vec::new();
+        let mut used = 0;
+        while let Some(segment) = self.next_segment().await? {
+            let tokens = tokenizer.count(&segment.raw);
+            if used + tokens <= budget {
+                used += tokens;
+                segments.push(segment);
+                continue;
             }
 
-            if !self.buffer.iter().all(|line| line.trim().is_empty()) {
-                return Ok(Some(self.buffer.join("\n")));
+            if segments.is_empty() {
+                let split = split_point(&segment.raw, budget, tokenizer);
+                let (head, tail) = self.split_segment(segment, split);
+                segments.push(head);
+                self.pushback.push_front(tail);
+            } else {
+                self.pushback.push_front(segment);
             }
+            break;
         }
+        Ok(segments)
     }
 
+    // 按段落或句子装入直到达到预算, 单独一个单位就超出预算时退回按行装入
+    async fn take_units(&mut self, mode: ChunkMode, budget: usize, tokenizer: &dyn Tokenizer) -> io::Result<Vec<Segment>> {
+        let mut segments = Vec::new();
+        let mut used = 0;
+        loop {
+            let unit = match mode {
+                ChunkMode::Sentences => self.read_sentence().await?,
+                _ => self.read_paragraph().await?,
+            };
+            if unit.is_empty() {
+                break;
+            }
 
+            let tokens: usize = unit.iter().map(|segment| tokenizer.count(&segment.raw)).sum();
+            if used + tokens <= budget {
+                used += tokens;
+                segments.extend(unit);
+                continue;
+            }
 
+            for segment in unit.into_iter().rev() {
+                self.pushback.push_front(segment);
+            }
+            if segments.is_empty() {
+                return self.take_tokens(budget, tokenizer).await;
+            }
+            break;
+        }
+        Ok(segments)
+    }
+
+    // 一段非空行加上其后的空行
+    async fn read_paragraph(&mut self) -> io::Result<Vec<Segment>> {
+        let mut unit = Vec::new();
+        let mut seen_text = false;
+        let mut seen_blank = false;
+        while let Some(segment) = self.next_segment().await? {
+            let blank = segment.raw.trim().is_empty();
+            if !blank && seen_blank && seen_text {
+                self.pushback.push_front(segment);
+                break;
+            }
+            seen_text |= !blank;
+            seen_blank |= blank && seen_text;
+            unit.push(segment);
+        }
+        Ok(unit)
+    }
 
+    // 到第一个句末为止, 句子可以跨行, 遇到空行也视为结束; 句末之后同一行的内容放回
+    async fn read_sentence(&mut self) -> io::Result<Vec<Segment>> {
+        let mut unit = Vec::new();
+        let mut seen_text = false;
+        while let Some(segment) = self.next_segment().await? {
+            if segment.raw.trim().is_empty() {
+                unit.push(segment);
+                if seen_text {
+                    break;
+                }
+                continue;
+            }
+            seen_text = true;
+
+            let content_len = segment.raw.trim_end().len();
+            match sentence_ends(&segment.raw).into_iter().find(|&end| end > 0) {
+                Some(end) if end < content_len => {
+                    let (head, tail) = self.split_segment(segment, end);
+                    unit.push(head);
+                    self.pushback.push_front(tail);
+                    break;
+                }
+                Some(_) => {
+                    unit.push(segment);
+                    break;
+                }
+                None => unit.push(segment),
+            }
+        }
+        Ok(unit)
+    }
 
     pub fn get_call_count(&self) -> usize {
         self.call_count
     }
+}
 
-    pub fn get_read_count(&self) -> usize {
-        self.read_count
-    }
+// 按字节范围重新读取一块源文本, 与 read_next_chunk 一样去掉行尾空白, 并去掉首尾的空行
+pub async fn read_source_range(
+    file_path: &str,
+    encoding: &'static Encoding,
+    start_byte: u64,
+    end_byte: u64
+) -> io::Result<String> {
+    let raw = read_source_text(file_path, encoding, start_byte, end_byte).await?;
+    Ok(trim_source_text(&raw))
+}
+
+pub fn trim_source_text(raw: &str) -> String {
+    let lines: Vec<&str> = raw.lines().map(str::trim_end).collect();
+    let first = lines.iter().position(|line| !line.is_empty()).unwrap_or(lines.len());
+    let last = lines.iter().rposition(|line| !line.is_empty()).map(|i| i + 1).unwrap_or(first);
+    lines[first..last].join("\n")
+}
+
+// 字节范围内解码后的原始文本, 保留空行、行首行尾的空白和换行符
+pub async fn read_source_text(
+    file_path: &str,
+    encoding: &'static Encoding,
+    start_byte: u64,
+    end_byte: u64
+) -> io::Result<String> {
+    let mut file = File::open(file_path).await?;
+    file.seek(SeekFrom::Start(start_byte)).await?;
+
+    let mut buffer = vec![0u8; (end_byte - start_byte) as usize];
+    file.read_exact(&mut buffer).await?;
+    Ok(decode(encoding, &buffer))
 }
 
 pub async fn write_txt_overwrite(folder: &str, filename: &str, content: &str) -> io::Result<()> {
-    let folder_path = Path::new(folder);
-    if !folder_path.exists() {
-        fs::create_dir_all(folder_path).await?;
-    }
+    write_bytes_overwrite(folder, filename, content.as_bytes()).await
+}
 
-    let path = folder_path.join(filename);
-    let mut file = File::create(path).await?;
-    file.write_all(content.as_bytes()).await?;
+pub async fn write_bytes_overwrite(folder: &str, filename: &str, content: &[u8]) -> io::Result<()> {
+    let path = Path::new(folder).join(filename);
+    if let Some(parent) = path.parent() {
+        if !parent.exists() {
+            fs::create_dir_all(parent).await?;
+        }
+    }
 
-    Ok(())
+    replace_file(&path, content).await
 }
 
-pub async fn write_txt_append(folder: &str, filename: &str, content: &str) -> io::Result<()> {
-    let folder_path = Path::new(folder);
-    if !folder_path.exists() {
-        fs::create_dir_all(folder_path).await?;
+// 把一块已编码的译文追加到输出文件, committed_len 是任务状态中记录的已提交长度
+// 上次写入中途失败留下的未提交内容会先被截断, 写入后同步到磁盘, 返回新的长度
+pub async fn write_bytes_append(folder: &str, filename: &str, content: &[u8], committed_len: u64) -> io::Result<u64> {
+    let path = Path::new(folder).join(filename);
+    if let Some(parent) = path.parent() {
+        if !parent.exists() {
+            fs::create_dir_all(parent).await?;
+        }
     }
 
-    let path = folder_path.join(filename);
     let mut file = OpenOptions::new()
         .create(true)
-        .append(true)
+        .truncate(false)
+        .write(true)
         .open(path).await?;
-    file.write_all(content.as_bytes()).await?;
+    if file.metadata().await?.len() != committed_len {
+        file.set_len(committed_len).await?;
+    }
+    file.seek(SeekFrom::Start(committed_len)).await?;
 
-    file.write_all(b"\n").await?;
+    file.write_all(content).await?;
+    file.sync_data().await?;
 
-    Ok(())
+    Ok(committed_len + content.len() as u64)
 }
 
 pub async fn write_json_overwrite<T: Serialize>(folder: &str, filename: &str, content: &T) -> io::Result<()> {
-    let folder_path = Path::new(folder);
-    if !folder_path.exists() {
-        fs::create_dir_all(folder_path).await?;
+    let path = Path::new(folder).join(filename);
+    if let Some(parent) = path.parent() {
+        if !parent.exists() {
+            fs::create_dir_all(parent).await?;
+        }
     }
 
-    let path = folder_path.join(filename);
-    let mut file = File::create(path).await?;
     let json_data = serde_json::to_string(content)?;
-    file.write_all(json_data.as_bytes()).await?;
+    replace_file(&path, json_data.as_bytes()).await
+}
 
+// 先写入同目录下的临时文件并同步到磁盘, 再重命名覆盖原文件
+// 中途崩溃时原文件保持不变, 不会留下写了一半的内容
+async fn replace_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut file = File::create(&temp_path).await?;
+    file.write_all(content).await?;
+    file.sync_all().await?;
+    drop(file);
+    fs::rename(&temp_path, path).await?;
+    sync_parent_dir(path).await
+}
+
+// 重命名本身也要同步所在目录才能保证断电后仍然生效, Windows 不支持打开目录
+#[cfg(unix)]
+async fn sync_parent_dir(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent).await?.sync_all().await
+}
+
+#[cfg(not(unix))]
+async fn sync_parent_dir(_path: &Path) -> io::Result<()> {
     Ok(())
 }
 
@@ -193,3 +525,76 @@ pub fn get_filename(file_path: &str) -> Result<String, Error> {
         None => Err(Error::new(ErrorKind::NotFound, "未找到文件名")),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenizer::CharRatioTokenizer;
+    use encoding_rs::{UTF_16LE, UTF_8};
+
+    const SOURCE: &str = "第一句。第二句！\nabc def\n\n新段落。\n";
+
+    // 按给定方式读完整个文件, 检查各块的字节位置首尾相接且与原文和哈希一致
+    async fn read_chunks(name: &str, bytes: &[u8], encoding: &'static Encoding, mode: ChunkMode, budget: usize) -> Vec<(String, u64, u64, usize, usize)> {
+        let path = std::env::temp_dir().join(format!("dify_translation_reader_{}_{}", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let tokenizer = Arc::new(CharRatioTokenizer { chars_per_token: 1.0 });
+        let strategy = ChunkStrategy::Packed { mode, budget, tokenizer };
+        let mut reader = LazyFileReader::new(&path.to_string_lossy(), encoding, strategy, 0, 0, false, None).await.unwrap();
+
+        let mut chunks = Vec::new();
+        let mut end_byte = bom_len(encoding, bytes) as u64;
+        while let Ok(Some(chunk)) = reader.read_next_chunk().await {
+            let range = &bytes[chunk.start_byte as usize..chunk.end_byte as usize];
+            assert_eq!(chunk.start_byte, end_byte);
+            assert_eq!(decode(encoding, range), chunk.raw);
+            assert_eq!(chunk.hash, format!("{:x}", Sha256::digest(range)));
+            end_byte = chunk.end_byte;
+            chunks.push((chunk.raw, chunk.start_byte, chunk.end_byte, chunk.start_line, chunk.end_line));
+        }
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(end_byte, bytes.len() as u64);
+        chunks
+    }
+
+    #[tokio::test]
+    async fn token_chunks_split_long_lines_at_sentence_ends() {
+        let chunks = read_chunks("tokens", SOURCE.as_bytes(), UTF_8, ChunkMode::Tokens, 5).await;
+        let ranges: Vec<_> = chunks.iter().map(|(raw, start, end, _, _)| (raw.as_str(), *start, *end)).collect();
+        assert_eq!(ranges, vec![("第一句。", 0, 12), ("第二句！\n", 12, 25), ("abc ", 25, 29), ("def\n\n", 29, 34), ("新段落。\n", 34, 47)]);
+    }
+
+    #[tokio::test]
+    async fn token_chunks_split_words_only_without_whitespace() {
+        let chunks = read_chunks("words", "abcdefgh\n".as_bytes(), UTF_8, ChunkMode::Tokens, 3).await;
+        let raws: Vec<_> = chunks.iter().map(|(raw, _, _, _, _)| raw.as_str()).collect();
+        assert_eq!(raws, vec!["abc", "def", "gh\n"]);
+    }
+
+    #[tokio::test]
+    async fn sentence_chunks_report_byte_offsets() {
+        let chunks = read_chunks("sentences", SOURCE.as_bytes(), UTF_8, ChunkMode::Sentences, 4).await;
+        let ranges: Vec<_> = chunks.iter().map(|(raw, start, end, _, _)| (raw.as_str(), *start, *end)).collect();
+        assert_eq!(ranges, vec![("第一句。", 0, 12), ("第二句！\n", 12, 25), ("abc ", 25, 29), ("def\n\n", 29, 34), ("新段落。\n", 34, 47)]);
+        let lines: Vec<_> = chunks.iter().map(|(_, _, _, start, end)| (*start, *end)).collect();
+        assert_eq!(lines, vec![(0, 0), (0, 1), (1, 1), (1, 3), (3, 4)]);
+    }
+
+    #[tokio::test]
+    async fn paragraph_chunks_report_byte_offsets() {
+        let chunks = read_chunks("paragraphs", SOURCE.as_bytes(), UTF_8, ChunkMode::Paragraphs, 15).await;
+        assert_eq!(chunks, vec![
+            ("第一句。第二句！\nabc def\n\n".to_string(), 0, 34, 0, 3),
+            ("新段落。\n".to_string(), 34, 47, 3, 4),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn utf16_chunks_report_source_byte_offsets() {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(SOURCE.encode_utf16().flat_map(u16::to_le_bytes));
+        let chunks = read_chunks("utf16", &bytes, UTF_16LE, ChunkMode::Sentences, 4).await;
+        let ranges: Vec<_> = chunks.iter().map(|(_, start, end, _, _)| (*start, *end)).collect();
+        assert_eq!(ranges, vec![(2, 10), (10, 20), (20, 28), (28, 38), (38, 48)]);
+    }
+}
@@ -0,0 +1,449 @@
+use crate::document::{placeholder, unmask, Document, Unit};
+use regex::Regex;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::LazyLock;
+
+static TAG: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^<(/?)([A-Za-z][A-Za-z0-9:_-]*)((?:[^>"']|"[^"]*"|'[^']*')*?)(/?)>"#).unwrap()
+});
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"([A-Za-z_:][-A-Za-z0-9_:.]*)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+)))?"#).unwrap()
+});
+static ENTITY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"&(?:[a-zA-Z][a-zA-Z0-9]*|#[0-9]+|#[xX][0-9a-fA-F]+);").unwrap()
+});
+
+// 段落中的行内元素, 翻译时替换为占位符, 其他元素的开始和结束都会切分段落
+const INLINE: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "br", "cite", "data", "del", "dfn", "em", "font", "i", "img", "ins", "kbd",
+    "label", "mark", "q", "rb", "rp", "rt", "ruby", "s", "samp", "small", "span", "strong", "sub", "sup", "time",
+    "u", "var", "wbr",
+];
+// 内容不翻译的元素, 在段落中时整个元素替换为一个占位符
+const SKIPPED: &[&str] = &["script", "style", "code", "kbd", "samp", "var", "svg", "math", "template"];
+// 内容不按标签解析的元素
+const RAW_TEXT: &[&str] = &["script", "style", "textarea", "title"];
+// 没有结束标签的元素
+const VOID: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr",
+];
+// 需要翻译的属性
+const ATTRIBUTES: &[&str] = &["alt", "title", "placeholder"];
+
+// HTML/XHTML 文档: 翻译文字节点和 alt、title、placeholder 属性, 标签按原文写回
+pub struct Html {
+    source: String,
+    segments: Vec<Segment>,
+    attributes: Vec<Attribute>,
+}
+
+// tag_ranges 是各占位符对应的标签在原文中的范围
+struct Segment {
+    range: Range<usize>,
+    unit: Unit,
+    tag_ranges: Vec<Range<usize>>,
+}
+
+// tag 是属性所在标签的范围, value 是属性值 (不含引号) 的范围
+struct Attribute {
+    tag: Range<usize>,
+    value: Range<usize>,
+    unit: Unit,
+}
+
+enum Token {
+    Text,
+    // 注释、CDATA、文档类型声明和处理指令
+    Other,
+    Tag { name: String, closing: bool, self_closing: bool, attributes: Range<usize> },
+}
+
+fn tokenize(source: &str) -> Vec<(Token, Range<usize>)> {
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    let mut text_start = 0;
+
+    while let Some(offset) = source[position..].find('<') {
+        let start = position + offset;
+        let rest = &source[start..];
+        let special = [("<!--", "-->"), ("<![CDATA[", "]]>"), ("<?", "?>"), ("<!", ">")]
+            .iter()
+            .find(|(open, _)| rest.starts_with(open))
+            .map(|(open, close)| rest[open.len()..].find(close).map_or(rest.len(), |end| open.len() + end + close.len()));
+
+        let (token, len) = if let Some(len) = special {
+            (Token::Other, len)
+        } else if let Some(captures) = TAG.captures(rest) {
+            let attributes = captures.get(3).unwrap();
+            let token = Token::Tag {
+                name: captures[2].to_ascii_lowercase(),
+                closing: !captures[1].is_empty(),
+                self_closing: !captures[4].is_empty(),
+                attributes: start + attributes.start()..start + attributes.end(),
+            };
+            (token, captures[0].len())
+        } else {
+            // 不是标签的 < 作为文字
+            position = start + 1;
+            continue;
+        };
+
+        if text_start < start {
+            tokens.push((Token::Text, text_start..start));
+        }
+        let end = start + len;
+        let raw = match &token {
+            Token::Tag { name, closing: false, self_closing: false, .. } if RAW_TEXT.contains(&name.as_str()) => Some(name.clone()),
+            _ => None,
+        };
+        tokens.push((token, start..end));
+        position = end;
+        text_start = end;
+
+        // script、style 等元素的内容直到结束标签为止都是文字
+        if let Some(name) = raw {
+            let close = format!("</{}", name);
+            let content_end = source[end..].to_ascii_lowercase().find(&close).map_or(source.len(), |i| end + i);
+            if end < content_end {
+                tokens.push((Token::Text, end..content_end));
+            }
+            position = content_end;
+            text_start = content_end;
+        }
+    }
+    if text_start < source.len() {
+        tokens.push((Token::Text, text_start..source.len()));
+    }
+    tokens
+}
+
+// 元素中的属性, 返回属性名和属性值的范围
+fn attributes(source: &str, range: Range<usize>) -> Vec<(String, Option<Range<usize>>)> {
+    ATTRIBUTE.captures_iter(&source[range.clone()])
+        .map(|captures| {
+            let value = captures.get(2).or(captures.get(3)).or(captures.get(4));
+            (captures[1].to_ascii_lowercase(), value.map(|value| range.start + value.start()..range.start + value.end()))
+        })
+        .collect()
+}
+
+// 文件中所有开始标签的名称和属性, 用于读取 EPUB 的 OPF 等 XML 文件
+pub fn start_tags(source: &str) -> Vec<(String, HashMap<String, String>)> {
+    tokenize(source).into_iter()
+        .filter_map(|(token, _)| match token {
+            Token::Tag { name, closing: false, attributes: range, .. } => {
+                let values = attributes(source, range).into_iter()
+                    .map(|(attribute, value)| (attribute, value.map_or(String::new(), |value| source[value].replace("&amp;", "&"))))
+                    .collect();
+                Some((name, values))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn is_skipped(source: &str, name: &str, attribute_range: &Range<usize>) -> bool {
+    SKIPPED.contains(&name)
+        || attributes(source, attribute_range.clone()).iter().any(|(attribute, value)| {
+            let value = value.as_ref().map_or("", |value| &source[value.clone()]);
+            (attribute == "translate" && value.eq_ignore_ascii_case("no"))
+                || (attribute == "class" && value.split_whitespace().any(|class| class == "notranslate"))
+        })
+}
+
+// 与开始标签对应的结束标签的位置, 没有时到文件末尾
+fn matching_end(tokens: &[(Token, Range<usize>)], start: usize, name: &str) -> usize {
+    let mut depth = 0;
+    for (i, (token, _)) in tokens.iter().enumerate().skip(start) {
+        if let Token::Tag { name: tag, closing, self_closing: false, .. } = token {
+            if tag == name {
+                if *closing {
+                    depth -= 1;
+                } else {
+                    depth += 1;
+                }
+                if depth == 0 {
+                    return i;
+                }
+            }
+        }
+    }
+    tokens.len() - 1
+}
+
+// 一段连续的行内内容, 每一块是 (范围, 是否需要翻译)
+struct Run {
+    pieces: Vec<(Range<usize>, bool)>,
+}
+
+pub fn parse(source: &str) -> Html {
+    let tokens = tokenize(source);
+    let mut runs = Vec::new();
+    let mut current: Option<Run> = None;
+    let mut attribute_units = Vec::new();
+
+    let mut i = 0;
+    while i < tokens.len() {
+        let (token, range) = &tokens[i];
+        match token {
+            Token::Text => {
+                let run = current.get_or_insert_with(|| Run { pieces: Vec::new() });
+                // 字符引用不翻译
+                let mut position = range.start;
+                for entity in ENTITY.find_iter(&source[range.clone()]) {
+                    let entity = range.start + entity.start()..range.start + entity.end();
+                    run.pieces.push((position..entity.start, true));
+                    run.pieces.push((entity.clone(), false));
+                    position = entity.end;
+                }
+                run.pieces.push((position..range.end, true));
+            }
+            Token::Other => {
+                if let Some(run) = &mut current {
+                    run.pieces.push((range.clone(), false));
+                }
+            }
+            Token::Tag { name, closing, self_closing, attributes: attribute_range } => {
+                let inline = INLINE.contains(&name.as_str());
+                if !closing && is_skipped(source, name, attribute_range) {
+                    // 跳过整个元素, 在段落中时作为一个占位符
+                    let end = if *self_closing || VOID.contains(&name.as_str()) { i } else { matching_end(&tokens, i, name) };
+                    let element = range.start..tokens[end].1.end;
+                    match &mut current {
+                        Some(run) if inline || name == "code" => run.pieces.push((element, false)),
+                        _ => runs.extend(current.take()),
+                    }
+                    i = end + 1;
+                    continue;
+                }
+
+                if !closing {
+                    for (attribute, value) in attributes(source, attribute_range.clone()) {
+                        if let Some(value) = value.filter(|value| ATTRIBUTES.contains(&attribute.as_str()) && !source[value.clone()].trim().is_empty()) {
+                            attribute_units.push((range.clone(), value));
+                        }
+                    }
+                }
+                if inline {
+                    current.get_or_insert_with(|| Run { pieces: Vec::new() }).pieces.push((range.clone(), false));
+                } else {
+                    runs.extend(current.take());
+                }
+            }
+        }
+        i += 1;
+    }
+    runs.extend(current);
+
+    let segments = runs.iter()
+        .filter_map(|run| mask_run(source, run))
+        .enumerate()
+        .map(|(i, (range, text, tag_ranges))| {
+            let tags = tag_ranges.iter().map(|tag| source[tag.clone()].to_string()).collect();
+            Segment { range, unit: Unit { id: (i + 1).to_string(), text, tags }, tag_ranges }
+        })
+        .collect();
+    let attributes = attribute_units.into_iter()
+        .enumerate()
+        .map(|(i, (tag, value))| {
+            let (text, tags) = crate::document::mask(&source[value.clone()], &ENTITY);
+            Attribute { tag, value, unit: Unit { id: format!("attr{}", i + 1), text, tags } }
+        })
+        .collect();
+    Html { source: source.to_string(), segments, attributes }
+}
+
+// 去掉首尾的空白, 标签替换为占位符, 相邻的合并; 只有标签和空白时返回 None
+// 返回段落的范围、文本和各占位符对应的标签范围
+fn mask_run(source: &str, run: &Run) -> Option<(Range<usize>, String, Vec<Range<usize>>)> {
+    let start = run.pieces.first()?.0.start;
+    let end = run.pieces.last()?.0.end;
+    let leading = source[start..end].len() - source[start..end].trim_start().len();
+    let trailing = source[start..end].len() - source[start..end].trim_end().len();
+    let range = start + leading..(end - trailing).max(start + leading);
+    if !run.pieces.iter().any(|(piece, translate)| *translate && !source[piece.clone()].trim().is_empty()) {
+        return None;
+    }
+
+    let mut text = String::new();
+    let mut tag_ranges: Vec<Range<usize>> = Vec::new();
+    let mut tag: Option<Range<usize>> = None;
+    for (piece, translate) in &run.pieces {
+        let piece = piece.start.max(range.start)..piece.end.min(range.end);
+        if piece.is_empty() {
+            continue;
+        }
+        if *translate {
+            if let Some(tag) = tag.take() {
+                text.push_str(&placeholder(tag_ranges.len()));
+                tag_ranges.push(tag);
+            }
+            text.push_str(&source[piece]);
+        } else {
+            // 一段中的各块首尾相接, 相邻的标签合并为一个范围
+            tag = Some(tag.map_or(piece.clone(), |tag| tag.start..piece.end));
+        }
+    }
+    if let Some(tag) = tag {
+        text.push_str(&placeholder(tag_ranges.len()));
+        tag_ranges.push(tag);
+    }
+    Some((range, text, tag_ranges))
+}
+
+impl Html {
+    // 替换了属性译文的标签
+    fn translated_tags(&self, translations: &HashMap<String, String>) -> Vec<(Range<usize>, String)> {
+        let mut tags: Vec<(Range<usize>, String)> = Vec::new();
+        for attribute in &self.attributes {
+            let Some(translation) = translations.get(&attribute.unit.id) else {
+                continue;
+            };
+            if tags.last().is_none_or(|(range, _)| *range != attribute.tag) {
+                tags.push((attribute.tag.clone(), self.source[attribute.tag.clone()].to_string()));
+            }
+            // 同一标签中的属性按位置从前往后替换, 之后的属性位置加上前面替换造成的长度变化
+            let (range, translated) = tags.last_mut().unwrap();
+            // 译文可能比原文短, 先加后减避免下溢
+            let start = attribute.value.start + translated.len() - range.end;
+            let value = start..start + attribute.value.len();
+            // 属性值中还需要转义引号
+            translated.replace_range(value, &translation.replace('"', "&quot;").replace('\'', "&#39;"));
+        }
+        tags
+    }
+
+    // 段落中的标签, 其中属性有译文的按占位符序号换成替换后的标签
+    fn segment_tags(&self, segment: &Segment, tags: &[(Range<usize>, String)]) -> Vec<String> {
+        segment.tag_ranges.iter()
+            .zip(&segment.unit.tags)
+            .map(|(tag_range, original)| {
+                let mut tag = original.clone();
+                // 从后往前替换, 前面标签的位置不受影响
+                for (range, translated) in tags.iter().rev() {
+                    if tag_range.start <= range.start && range.end <= tag_range.end {
+                        tag.replace_range(range.start - tag_range.start..range.end - tag_range.start, translated);
+                    }
+                }
+                tag
+            })
+            .collect()
+    }
+}
+
+impl Document for Html {
+    fn units(&self) -> Vec<Unit> {
+        let segments = self.segments.iter().map(|segment| &segment.unit);
+        let attributes = self.attributes.iter().map(|attribute| &attribute.unit);
+        segments.chain(attributes)
+            .map(|unit| Unit { id: unit.id.clone(), text: unit.text.clone(), tags: unit.tags.clone() })
+            .collect()
+    }
+
+    fn render(&self, translations: &HashMap<String, String>) -> String {
+        let tags = self.translated_tags(translations);
+
+        // 段落中的标签随译文一起写回, 段落之外的标签直接替换
+        let mut replacements: Vec<(Range<usize>, String)> = Vec::new();
+        for segment in &self.segments {
+            let text = translations.get(&segment.unit.id)
+                .cloned()
+                .unwrap_or_else(|| unmask(&segment.unit.text, &self.segment_tags(segment, &tags)));
+            replacements.push((segment.range.clone(), text));
+        }
+        for (range, translated) in &tags {
+            if !self.segments.iter().any(|segment| segment.range.start <= range.start && range.end <= segment.range.end) {
+                replacements.push((range.clone(), translated.clone()));
+            }
+        }
+        replacements.sort_by_key(|(range, _)| range.start);
+
+        let mut output = String::new();
+        let mut position = 0;
+        for (range, text) in replacements {
+            output.push_str(&self.source[position..range.start]);
+            output.push_str(&text);
+            position = range.end;
+        }
+        output.push_str(&self.source[position..]);
+        output
+    }
+
+    // 译文中新出现的 & < > 需要转义
+    fn escape(&self, translation: &str) -> String {
+        translation.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+    }
+
+    // 先还原属性的译文, 段落中包含这些属性的标签再按占位符序号换成替换后的标签
+    fn restore_tags(&self, translations: &HashMap<String, String>) -> HashMap<String, String> {
+        let mut restored: HashMap<String, String> = self.attributes.iter()
+            .filter_map(|attribute| {
+                let translation = translations.get(&attribute.unit.id)?;
+                Some((attribute.unit.id.clone(), unmask(translation, &attribute.unit.tags)))
+            })
+            .collect();
+        let tags = self.translated_tags(&restored);
+        for segment in &self.segments {
+            if let Some(translation) = translations.get(&segment.unit.id) {
+                restored.insert(segment.unit.id.clone(), unmask(translation, &self.segment_tags(segment, &tags)));
+            }
+        }
+        restored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "<!DOCTYPE html>\n<html><head><title>标题</title><style>p { color: red; }</style></head>\n<body>\n<!-- 注释 -->\n<p>第一段 <b>加粗</b> &amp; <code>x &lt; y</code>。</p>\n<img src=\"a.png\" alt=\"图片说明\" title='提示'>\n<p translate=\"no\">不翻译</p>\n<ul><li>甲</li><li>乙<br/>丙</li></ul>\n</body></html>\n";
+
+    #[test]
+    fn extracts_text_and_attributes() {
+        let units = parse(SOURCE).units();
+        let texts: Vec<&str> = units.iter().map(|unit| unit.text.as_str()).collect();
+        assert!(texts.contains(&"标题"));
+        assert!(texts.contains(&"第一段 [[0]]加粗[[1]] [[2]] [[3]]。"));
+        assert!(texts.contains(&"图片说明"));
+        assert!(texts.contains(&"提示"));
+        assert!(!texts.iter().any(|text| text.contains("不翻译") || text.contains("color") || text.contains("注释")));
+    }
+
+    #[test]
+    fn skips_code_scripts_and_notranslate_elements() {
+        let source = "<p>运行 <code>ls</code> 和 <span class=\"x notranslate\">品牌</span></p><script>var a = \"文字\";</script><div translate=\"NO\"><p>保留</p></div><p>末尾</p>";
+        let html = parse(source);
+        let texts: Vec<String> = html.units().into_iter().map(|unit| unit.text).collect();
+        assert_eq!(texts, vec!["运行 [[0]] 和 [[1]]", "末尾"]);
+
+        let translations = html.restore_tags(&HashMap::from([("1".to_string(), "Run [[0]] and [[1]]".to_string())]));
+        assert_eq!(
+            html.render(&translations),
+            "<p>Run <code>ls</code> and <span class=\"x notranslate\">品牌</span></p><script>var a = \"文字\";</script><div translate=\"NO\"><p>保留</p></div><p>末尾</p>"
+        );
+    }
+
+    #[test]
+    fn replaces_attributes_of_different_lengths() {
+        let html = parse("<img alt=\"很长的图片说明\" title=\"短\">");
+        let ids: Vec<String> = html.units().into_iter().map(|unit| unit.id).collect();
+        let translations = HashMap::from([(ids[0].clone(), "A".to_string()), (ids[1].clone(), "a \"long\" title".to_string())]);
+        assert_eq!(html.render(&translations), "<img alt=\"A\" title=\"a &quot;long&quot; title\">");
+    }
+
+    #[test]
+    fn maps_translated_attributes_by_placeholder_index() {
+        let html = parse("<p>看 <img alt=\"图\"> 和 <img alt=\"图\"></p>");
+        let translations = HashMap::from([
+            ("1".to_string(), "[[1]] and [[0]]".to_string()),
+            ("attr1".to_string(), "first".to_string()),
+            ("attr2".to_string(), "second".to_string()),
+        ]);
+        let restored = html.restore_tags(&translations);
+        assert_eq!(html.render(&restored), "<p><img alt=\"second\"> and <img alt=\"first\"></p>");
+
+        let untranslated = HashMap::from([("attr2".to_string(), "second".to_string())]);
+        assert_eq!(html.render(&html.restore_tags(&untranslated)), "<p>看 <img alt=\"图\"> 和 <img alt=\"second\"></p>");
+    }
+}
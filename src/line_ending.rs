@@ -0,0 +1,89 @@
+use serde::Deserialize;
+
+// preserve 按原文每行的换行符写入译文, 对齐模式下同时保留行尾空白
+// lf 和 crlf 统一换行符, 行尾空白不保留
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum LineEndings {
+    #[default]
+    Preserve,
+    Lf,
+    Crlf,
+}
+
+impl LineEndings {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "preserve" => Ok(LineEndings::Preserve),
+            "lf" => Ok(LineEndings::Lf),
+            "crlf" => Ok(LineEndings::Crlf),
+            _ => Err(format!("无效的换行符: {}, 可以使用 preserve、lf 或 crlf", name)),
+        }
+    }
+
+    // 对齐后的一行译文使用的换行符; 原文这一行没有换行符时 (文件末尾或行内切开) 译文也没有
+    pub fn line_terminator(&self, source_line: &str) -> &'static str {
+        match (terminator(source_line), self) {
+            ("", _) => "",
+            (source, LineEndings::Preserve) => source,
+            (_, LineEndings::Lf) => "\n",
+            (_, LineEndings::Crlf) => "\r\n",
+        }
+    }
+
+    // 原文行尾的空白, 只在 preserve 时保留; 行内切开处的空白是与下一块之间的分隔, 总是保留
+    pub fn trailing_whitespace<'a>(&self, source_line: &'a str) -> &'a str {
+        if *self != LineEndings::Preserve && !terminator(source_line).is_empty() {
+            return "";
+        }
+        let body = source_line.trim_end_matches(['\n', '\r']);
+        &body[body.trim_end().len()..]
+    }
+
+    // 不对齐时整块译文统一使用一种换行符: preserve 时原文中有 CRLF 就用 CRLF
+    // 原文以换行符结束时末尾加上换行符; 原文在行内切开或是没有换行符的最后一行时不加, 只保留切开处的空白
+    pub fn format(&self, text: &str, source: &str) -> String {
+        let style = match self {
+            LineEndings::Preserve if source.contains("\r\n") => "\r\n",
+            LineEndings::Preserve | LineEndings::Lf => "\n",
+            LineEndings::Crlf => "\r\n",
+        };
+        let lines: Vec<&str> = text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).collect();
+        let end = if terminator(source).is_empty() { self.trailing_whitespace(source) } else { style };
+        format!("{}{}", lines.join(style), end)
+    }
+}
+
+// 一行原文末尾的换行符
+pub fn terminator(line: &str) -> &'static str {
+    if line.ends_with("\r\n") {
+        "\r\n"
+    } else if line.ends_with('\n') {
+        "\n"
+    } else {
+        ""
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_follows_source_terminator() {
+        assert_eq!(LineEndings::Preserve.format("A\nB", "a\r\nb\r\n"), "A\r\nB\r\n");
+        assert_eq!(LineEndings::Lf.format("A\r\nB", "a\r\nb\r\n"), "A\nB\n");
+    }
+
+    #[test]
+    fn format_adds_nothing_after_chunk_ending_mid_line() {
+        assert_eq!(LineEndings::Preserve.format("第一句。", "第一句。"), "第一句。");
+        assert_eq!(LineEndings::Crlf.format("The first", "The first "), "The first ");
+    }
+
+    #[test]
+    fn format_keeps_unterminated_last_line() {
+        assert_eq!(LineEndings::Preserve.format("A\nB", "a\nb"), "A\nB");
+        assert_eq!(LineEndings::Crlf.format("A\nB", "a\r\nb"), "A\r\nB");
+    }
+}
@@ -1,57 +1,343 @@
+mod align;
 mod api;
+mod ass;
+mod chapters;
+mod chunking;
 mod config;
+mod context;
+mod document;
+mod dry_run;
+mod encoding;
+mod epub;
 mod file_operations;
-
+mod html;
+mod line_ending;
+mod markdown;
+mod pipeline;
+mod po;
+mod progress;
+mod project;
+mod retry;
+mod shutdown;
+mod srt;
+mod state;
+mod tokenizer;
+mod vtt;
+
+use crate::align::{AlignOptions, DEFAULT_ALIGN_RETRIES};
+use crate::chapters::ChapterMatcher;
+use crate::chunking::{ChunkMode, ChunkingOptions};
 use crate::config::{ConfigData, load_config_from_file, load_api_config, APIConfig};
+use crate::document::{DocumentOptions, DocumentState, Format};
+use crate::dry_run::{dry_run, DryRunFlags, EstimateSettings, DEFAULT_PROMPT_TOKENS};
+use crate::encoding::EncodingOptions;
 use crate::file_operations::{
-    read_file_content, write_json_overwrite, write_txt_append, write_txt_overwrite,
-    check_file_exists, get_filename, remove_extension, LazyFileReader, CONFIG_DIR, TERM_DIR, TRANSLATION_DIR
+    read_file_content, write_json_overwrite, check_file_exists, get_filename, remove_extension, CONFIG_DIR, TERM_DIR, TRANSLATION_DIR
 };
-use api::{run_workflow, Input, RequestData};
-use serde_json::Value;
+use crate::line_ending::LineEndings;
+use crate::pipeline::{default_reorder_window, run_jobs, state_file_name, Job, PipelineOptions, DEFAULT_SHUTDOWN_GRACE_SECS};
+use crate::retry::{retry_chunks, ChunkSelection, RetryOptions};
 use std::io::{self, Write};
+use std::path::Path;
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
-use tokio::sync::mpsc::Sender;
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let reconcile = args.iter().any(|arg| arg == "--reconcile");
+    let chunks = flag_value(&args, "--chunks");
+    let output_key = flag_value(&args, "--output-key").unwrap_or("output");
+    let task_num = flag_value(&args, "--task-num").and_then(|n| n.parse().ok()).unwrap_or(4);
+    let window = flag_value(&args, "--window").and_then(|n| n.parse().ok());
+    let grace = flag_value(&args, "--grace").and_then(|n| n.parse().ok());
+    let max_tokens = flag_value(&args, "--max-tokens").and_then(|n| n.parse().ok());
+    let tokenizer = flag_value(&args, "--tokenizer");
+    let chunk_by = flag_value(&args, "--chunk-by");
+    let prompt_tokens = flag_value(&args, "--prompt-tokens").and_then(|n| n.parse().ok());
+    let context_lines = flag_value(&args, "--context-lines").and_then(|n| n.parse().ok());
+    api::set_verbose(args.iter().any(|arg| arg == "--verbose"));
+    let (encodings, line_endings) = match parse_output_flags(&args) {
+        Ok(flags) => flags,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let chapters = match parse_chapter_flags(&args) {
+        Ok(chapters) => chapters,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let chapter_files = args.iter().any(|arg| arg == "--chapter-files");
+    let documents = DocumentOptions {
+        keep_original: args.iter().any(|arg| arg == "--keep-original"),
+        front_matter: flag_value(&args, "--front-matter")
+            .map(|keys| keys.split(',').map(|key| key.trim().to_string()).filter(|key| !key.is_empty()).collect())
+            .unwrap_or_default(),
+        include_fuzzy: args.iter().any(|arg| arg == "--include-fuzzy"),
+        mark_fuzzy: args.iter().any(|arg| arg == "--mark-fuzzy"),
+    };
+    let preserve_layout = args.iter().any(|arg| arg == "--preserve-layout");
+    let alignment = (preserve_layout || args.iter().any(|arg| arg == "--align-lines")).then(|| AlignOptions {
+        retries: flag_value(&args, "--align-retries").and_then(|n| n.parse().ok()).unwrap_or(DEFAULT_ALIGN_RETRIES),
+        preserve_layout,
+        line_endings,
+    });
+    let dry_run = args.iter().any(|arg| arg == "--dry-run").then(|| DryRunFlags {
+        price: flag_value(&args, "--price").and_then(|n| n.parse().ok()),
+        prompt_tokens,
+        export: flag_value(&args, "--export").map(str::to_string),
+    });
+    let args: Vec<&str> = args.iter().map(String::as_str).filter(|arg| !arg.starts_with("--")).collect();
+
+    let result = match args.as_slice() {
+        [] => {
+            let flags = InteractiveFlags { reconcile, window, grace, max_tokens, tokenizer, chunk_by, prompt_tokens, context_lines, alignment, encodings, line_endings, chapters, chapter_files, documents };
+            translate_interactive(flags, dry_run).await
+        }
+        ["status", input_file_path] => show_status(input_file_path, &encodings).map(|_| 0),
+        ["retry-failed", input_file_path] => {
+            let options = RetryOptions { task_num, output_key: output_key.to_string(), alignment, line_endings, chapter_files };
+            retry_file(input_file_path, &encodings, options, chunks).await.map(|_| 0)
+        }
+        ["project", "translate", manifest_path] => match dry_run {
+            Some(flags) => project::dry_run_project(manifest_path, flags).await.map(|_| 0),
+            None => project::translate_project(manifest_path, reconcile, grace).await,
+        },
+        ["project", "status", manifest_path] => project::show_project_status(manifest_path).map(|_| 0),
+        ["project", "retry-failed", manifest_path] => project::retry_project(manifest_path, chunks).await.map(|_| 0),
+        _ => {
+            print_usage();
+            Ok(1)
+        }
+    };
+
+    let exit_code = result.unwrap_or_else(|e| {
+        println!("{}", e);
+        1
+    });
+    std::process::exit(exit_code);
+}
+
+fn print_usage() {
+    println!("用法:");
+    println!("  dify_translation                               交互式翻译单个文件");
+    println!("  dify_translation status <file>                 查看单个文件的翻译进度");
+    println!("  dify_translation project translate <manifest>  按项目清单翻译多个文件");
+    println!("  dify_translation project status <manifest>     查看项目中各文件的翻译进度");
+    println!("  dify_translation retry-failed <file>           重新翻译单个文件中失败的块");
+    println!("  dify_translation project retry-failed <manifest>");
+    println!("                                                 重新翻译项目中失败的块");
+    println!();
+    println!("选项:");
+    println!("  --reconcile        源文件或输出文件与记录不一致时, 回退到最后一个一致的位置继续翻译");
+    println!("  --chunks=3,5-7     retry-failed 时只重新翻译指定的块, 不论是否失败");
+    println!("  --output-key=KEY   retry-failed 单个文件时的输出变量名, 默认为output");
+    println!("  --task-num=N       retry-failed 单个文件时的并发数, 默认为4");
+    println!("  --window=N         交互式翻译时最多允许多少块已分发但尚未写入, 默认为task_num的4倍");
+    println!("  --grace=SECS       收到中断信号后等待翻译中的块完成的秒数, 默认为30, 0表示不等待");
+    println!("  --max-tokens=N     交互式翻译时按tokens预算切分, 不再询问num_lines, 术语表和提示词会先从预算中扣除");
+    println!("  --chunk-by=MODE    交互式翻译时的切分方式: lines、tokens (默认)、paragraphs 或 sentences");
+    println!("  --tokenizer=NAME   估算tokens的方式: cjk (默认) 或 chars:N (每N个字符一个token)");
+    println!("  --context-lines=N  交互式翻译时把前N行原文和译文作为上文发送给工作流, 同一文件的块将依次翻译");
+    println!("  --align-lines      检查译文与原文的行数是否一致, 不一致时重新请求、切分或逐行翻译, 空行原样保留");
+    println!("  --align-retries=N  --align-lines 时行数不一致先重新请求的次数, 默认为1");
+    println!("  --preserve-layout  保留源文件的空行和缩进, 只把正文发送给工作流, 同时开启 --align-lines");
+    println!("  --encoding=NAME    源文件的编码, 例如 shift_jis、gbk、gb18030、big5、utf-16le, 默认自动检测");
+    println!("  --output-encoding=NAME");
+    println!("                     交互式翻译和 retry-failed 时输出文件的编码, 默认为utf-8");
+    println!("  --output-bom       输出文件开头写入 BOM, 只对 UTF-8 和 UTF-16 有效");
+    println!("  --line-endings=STYLE");
+    println!("                     输出文件的换行符: preserve (默认, 与源文件相同, 对齐模式下同时保留行尾空白)、lf 或 crlf");
+    println!("  --chapters=zh,ja,en");
+    println!("                     交互式翻译时按内置的章节标题格式切分, 块不跨越章节, 章节标题单独成块");
+    println!("  --chapter-pattern=REGEX");
+    println!("                     自定义的章节标题正则, 匹配去掉首尾空白的一行, 可以与 --chapters 同时使用");
+    println!("  --chapter-files    翻译和 retry-failed 后把输出按章节拆分到与输出文件同名的目录中, 每章一个文件");
+    println!("  --keep-original    翻译 ASS/SSA 字幕时把原文保留为注释事件");
+    println!("  --front-matter=title,description");
+    println!("                     翻译 Markdown 时同时翻译前置元数据中的这些字段");
+    println!("  --include-fuzzy    翻译 PO 文件时同时重新翻译标记为 fuzzy 的条目");
+    println!("  --mark-fuzzy       翻译 PO 文件时把译文标记为 fuzzy, 等待校对");
+    println!("  --dry-run          只切分源文件并估算tokens和费用, 不调用工作流");
+    println!("  --price=N          --dry-run 时每百万tokens的价格");
+    println!("  --prompt-tokens=N  工作流提示词的tokens数, 用于按预算切分和 --dry-run 估算, 默认为200");
+    println!("  --export=FILE      --dry-run 时把每块的信息导出为csv或json, 不在终端中逐块打印");
+    println!("  --verbose          打印发送给工作流的请求和收到的事件数据, 同时关闭终端中的进度显示");
+    println!();
+    println!("退出码: 0 全部完成, 1 出错, 2 被中断, 3 已结束但有失败的块");
+}
+
+fn flag_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().find_map(|arg| arg.strip_prefix(name)?.strip_prefix('='))
+}
+
+fn parse_output_flags(args: &[String]) -> Result<(EncodingOptions, LineEndings), String> {
+    let encodings = EncodingOptions::parse(
+        flag_value(args, "--encoding"),
+        flag_value(args, "--output-encoding"),
+        args.iter().any(|arg| arg == "--output-bom"),
+    )?;
+    let line_endings = flag_value(args, "--line-endings").map(LineEndings::parse).transpose()?;
+    Ok((encodings, line_endings.unwrap_or_default()))
+}
+
+// --chapters 和 --chapter-pattern 都未指定时不按章节切分
+fn parse_chapter_flags(args: &[String]) -> Result<Option<Arc<ChapterMatcher>>, String> {
+    let presets: Vec<String> = flag_value(args, "--chapters")
+        .map(|names| names.split(',').map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).collect())
+        .unwrap_or_default();
+    let patterns: Vec<String> = flag_value(args, "--chapter-pattern").map(str::to_string).into_iter().collect();
+    if presets.is_empty() && patterns.is_empty() {
+        return Ok(None);
+    }
+    ChapterMatcher::new(&presets, &patterns).map(|matcher| Some(Arc::new(matcher)))
+}
+
+struct InteractiveFlags<'a> {
+    reconcile: bool,
+    window: Option<usize>,
+    grace: Option<u64>,
+    max_tokens: Option<usize>,
+    tokenizer: Option<&'a str>,
+    chunk_by: Option<&'a str>,
+    prompt_tokens: Option<usize>,
+    context_lines: Option<usize>,
+    alignment: Option<AlignOptions>,
+    encodings: EncodingOptions,
+    line_endings: LineEndings,
+    chapters: Option<Arc<ChapterMatcher>>,
+    chapter_files: bool,
+    documents: DocumentOptions,
+}
+
+// 指定了 --max-tokens 时按预算切分, 否则询问 num_lines
+fn get_chunking(flags: &InteractiveFlags, prompt_tokens: usize) -> Result<ChunkingOptions, String> {
+    let mode = flags.chunk_by.map(ChunkMode::parse).transpose()?;
+    let mut chunking = match (flags.max_tokens, mode) {
+        (_, Some(ChunkMode::Lines)) | (None, None) => ChunkingOptions::lines(get_num_lines(), prompt_tokens),
+        (Some(max_tokens), mode) => {
+            ChunkingOptions::packed(mode.unwrap_or(ChunkMode::Tokens), max_tokens, prompt_tokens, flags.tokenizer)?
+        }
+        (None, Some(_)) => return Err("--chunk-by 不是 lines 时需要同时指定 --max-tokens".to_string()),
+    };
+    chunking.chapters = flags.chapters.clone();
+    Ok(chunking)
+}
+
+async fn translate_interactive(flags: InteractiveFlags<'_>, dry_run_flags: Option<DryRunFlags>) -> Result<i32, String> {
     let input_file_path = get_input_file_path();
     if !check_file_exists(&input_file_path) {
-        println!("文件不存在: {}", input_file_path);
-        return;
+        return Err(format!("文件不存在: {}", input_file_path));
     }
 
     let input_file_name = get_filename(&input_file_path).unwrap();
     let input_file_base_name = remove_extension(&input_file_name);
 
-    let config_data = load_config_from_file(&input_file_path).unwrap_or_else(|| create_default_config());
-    let config_data = Arc::new(config_data);
+    // 新文件的默认配置在真正翻译前才写入, 预览不会创建配置文件
+    let (config_data, is_new_config) = match load_config_from_file(&input_file_path) {
+        Some(config_data) => (config_data, false),
+        None => (create_default_config(), true),
+    };
     let term = get_term_file_path(&input_file_base_name);
 
+    let job = build_job(&input_file_path, &input_file_base_name, config_data, term, &flags.encodings);
+    if let Some(dry_run_flags) = dry_run_flags {
+        let estimate = EstimateSettings::default();
+        let chunking = get_chunking(&flags, dry_run_flags.prompt_tokens(&estimate))?;
+        dry_run(&[job], &dry_run_flags.into_options(chunking, &estimate)).await?;
+        return Ok(0);
+    }
+
+    if is_new_config {
+        write_json_overwrite(CONFIG_DIR, &format!("{}.json", input_file_base_name), &job.config_data).await.unwrap();
+    }
+
     let output_key = get_output_key();
-    let num_lines = get_num_lines();
+    let chunking = get_chunking(&flags, flags.prompt_tokens.unwrap_or(DEFAULT_PROMPT_TOKENS))?;
     let task_num = get_task_num();
 
-    let (tx, rx) = mpsc::channel::<(usize, usize, Result<Value, String>)>(1024);
-
-    let reader = Arc::new(Mutex::new(LazyFileReader::new(&input_file_path, num_lines, config_data.history_lines).await.unwrap()));
     let api_config = Arc::new(get_api_config().unwrap());
 
-    let handles = spawn_translation_tasks(
+    let options = PipelineOptions {
         task_num,
-        Arc::clone(&api_config),
-        Arc::clone(&config_data),
-        Arc::clone(&term),
-        Arc::clone(&reader),
-        tx.clone()
-    ).await;
+        chunking,
+        output_key,
+        reconcile: flags.reconcile,
+        reorder_window: flags.window.unwrap_or_else(|| default_reorder_window(task_num)),
+        shutdown_grace_secs: flags.grace.unwrap_or(DEFAULT_SHUTDOWN_GRACE_SECS),
+        context_lines: flags.context_lines.unwrap_or(0),
+        alignment: flags.alignment,
+        line_endings: flags.line_endings,
+        chapter_files: flags.chapter_files,
+        documents: flags.documents,
+    };
+
+    let summary = run_jobs(vec![job], api_config, options).await?;
+    Ok(summary.exit_code())
+}
+
+fn build_job(
+    input_file_path: &str,
+    input_file_base_name: &str,
+    config_data: ConfigData,
+    term: Arc<String>,
+    encodings: &EncodingOptions
+) -> Job {
+    let format = Format::from_path(input_file_path);
+    Job {
+        name: input_file_base_name.to_string(),
+        source_path: input_file_path.to_string(),
+        output_dir: TRANSLATION_DIR.to_string(),
+        output_file_name: format!(
+            "{}_{}2{}.{}",
+            input_file_base_name, config_data.source_lang, config_data.target_lang, format.extension()
+        ),
+        state_file_name: state_file_name(input_file_base_name, &config_data, format),
+        legacy_config_path: Some(Path::new(CONFIG_DIR).join(format!("{}.json", input_file_base_name))),
+        term_file_name: Some(format!("{}_term.txt", input_file_base_name)),
+        config_data,
+        term,
+        source_encoding: encodings.source_encoding(input_file_path),
+        output_encoding: encodings.output,
+        format,
+    }
+}
+
+// 根据已有的配置创建任务, 术语表使用默认路径
+fn load_existing_job(input_file_path: &str, encodings: &EncodingOptions) -> Result<Job, String> {
+    let input_file_name = get_filename(input_file_path).map_err(|e| e.to_string())?;
+    let input_file_base_name = remove_extension(&input_file_name);
+    let config_data = load_config_from_file(input_file_path)
+        .ok_or_else(|| format!("没有找到 {} 的配置", input_file_path))?;
+
+    let term_path = Path::new(TERM_DIR).join(format!("{}_term.txt", input_file_base_name));
+    let term = read_file_content(&term_path.to_string_lossy()).unwrap_or_default();
 
-    process_results(task_num, tx, rx, num_lines, &output_key, &input_file_base_name, &config_data, &term).await;
+    Ok(build_job(input_file_path, &input_file_base_name, config_data, Arc::new(term), encodings))
+}
 
-    for handle in handles {
-        handle.await.unwrap();
+fn show_status(input_file_path: &str, encodings: &EncodingOptions) -> Result<(), String> {
+    let job = load_existing_job(input_file_path, encodings)?;
+    match job.format {
+        Format::Text => job.load_state()?.print_summary(),
+        _ => DocumentState::load(&job)?.print_summary(),
     }
+    Ok(())
+}
+
+async fn retry_file(
+    input_file_path: &str,
+    encodings: &EncodingOptions,
+    options: RetryOptions,
+    chunks: Option<&str>
+) -> Result<(), String> {
+    let selection = ChunkSelection::parse(chunks)?;
+    let job = load_existing_job(input_file_path, encodings)?;
+    let api_config = Arc::new(get_api_config()?);
+    retry_chunks(vec![job], api_config, options, &selection).await
 }
 
 fn get_input_file_path() -> String {
@@ -98,13 +384,12 @@ fn create_default_config() -> ConfigData {
     ConfigData {
         target_lang,
         source_lang,
-        history_lines: 0,
     }
 }
 
 fn get_api_config() -> Result<APIConfig, String> {
     let config_path = format!("{}/user.yaml", CONFIG_DIR);
-    let api_config = load_api_config(&config_path).map_err(|e| e.to_string())?;
+    let api_config = load_api_config(&config_path, None).map_err(|e| e.to_string())?;
     Ok(api_config)
 }
 
@@ -128,168 +413,3 @@ fn get_term_file_path(input_file_base_name: &str) -> Arc<String> {
         Arc::new(String::new())
     }
 }
-
-async fn spawn_translation_tasks(
-    task_num: usize,
-    api_config: Arc<APIConfig>,
-    config_data: Arc<ConfigData>,
-    term: Arc<String>,
-    reader: Arc<Mutex<LazyFileReader>>,
-    tx: Sender<(usize, usize, Result<Value, String>)>
-) -> Vec<tokio::task::JoinHandle<()>> {
-    let mut handles = Vec::new();
-
-    for i in 0..task_num {
-        println!("正在创建工作流{}...\n", i);
-        let config_data = Arc::clone(&config_data);
-        let term = Arc::clone(&term);
-        let reader = Arc::clone(&reader);
-        let tx = tx.clone();
-        let api_config = Arc::clone(&api_config);
-
-        let handle = tokio::spawn(create_task(i, api_config, config_data, term, reader, tx));
-        handles.push(handle);
-    }
-
-    handles
-}
-
-async fn create_task(
-    task_id: usize,
-    api_config: Arc<APIConfig>,
-    config_data: Arc<ConfigData>,
-    term: Arc<String>,
-    reader: Arc<Mutex<LazyFileReader>>,
-    tx: Sender<(usize, usize, Result<Value, String>)>
-) -> () {
-    loop {
-        println!("工作流{}正在读取下一块数据...\n", task_id);
-        let (chunk, count, read_count);
-        {
-            let mut reader = reader.lock().await;
-            chunk = reader.read_next_chunk().await;
-            count = reader.get_call_count();
-            read_count = reader.get_read_count();
-        }
-
-        if let Ok(Some(value)) = chunk {
-            let result = process_task(&config_data, &api_config, &term, value).await;
-            tx.send((count, read_count, result)).await.unwrap();
-        } else {
-            println!("工作流{}已结束\n", task_id);
-            tx.send((0, 0, Err("文件结束".to_string()))).await.unwrap();
-            break;
-        }
-    }
-}
-
-async fn process_task(config_data: &Arc<ConfigData>, api_config: &Arc<APIConfig>, term: &Arc<String>, value: String) -> Result<Value, String> {
-    let user_id = "fww";
-    let response_mode = "streaming";
-    let input = Input::new(&config_data.target_lang, value, &config_data.source_lang, &term);
-    let request_data = RequestData::new(input, response_mode, user_id);
-    let result = run_workflow(&api_config.api_key, &api_config.base_url, &request_data).await;
-
-    match result {
-        Ok(Some(outputs)) => Ok(outputs),
-        Ok(None) => Err("返回结果为空".to_string()),
-        Err(err) => Err(format!("请求出错: {}", err)),
-    }
-}
-
-async fn process_results(
-    task_num: usize,
-    tx: Sender<(usize, usize, Result<Value, String>)>,
-    mut rx: mpsc::Receiver<(usize, usize, Result<Value, String>)>,
-    num_lines: usize,
-    output_key: &str,
-    input_file_base_name: &str,
-    config_data: &ConfigData,
-    term: &Arc<String>
-) {
-    let mut received = 0;
-    let mut end = 0;
-
-    loop {
-        if end == task_num && rx.is_empty() {
-            drop(tx);
-            break;
-        }
-
-        if let Some((count, read_count, result)) = rx.recv().await {
-            handle_message(count, read_count, result, &mut received, &mut end, num_lines, output_key, input_file_base_name, config_data, term, &tx).await;
-        }
-    }
-}
-
-async fn handle_message(
-    count: usize,
-    read_count: usize,
-    result: Result<Value, String>,
-    received: &mut usize,
-    end: &mut usize,
-    num_lines: usize,
-    output_key: &str,
-    input_file_base_name: &str,
-    config_data: &ConfigData,
-    term: &Arc<String>,
-    tx: &Sender<(usize, usize, Result<Value, String>)>
-) {
-    if count == 0 {
-        *end += 1;
-    } else if count == *received + 1 {
-        process_normal_result(count, read_count, result, output_key, input_file_base_name, config_data, term, num_lines).await;
-        *received += 1;
-    } else {
-        tx.send((count, read_count, result)).await.unwrap();
-    }
-}
-
-async fn process_normal_result(
-    count: usize,
-    read_count: usize,
-    result: Result<Value, String>,
-    output_key: &str,
-    input_file_base_name: &str,
-    config_data: &ConfigData,
-    term: &Arc<String>,
-    num_lines: usize
-) {
-    if let Ok(data) = result {
-        let translation = data.get(output_key).unwrap().as_str().unwrap();
-        write_translation_to_file(input_file_base_name, config_data, translation).await;
-        write_term_if_needed(term, input_file_base_name).await;
-        update_config_data(config_data, input_file_base_name, config_data.history_lines + read_count * num_lines).await;
-        println!("chunk {} 已返回结果", count);
-    } else {
-        println!("chunk {} 未返回结果", count);
-    }
-}
-
-async fn write_translation_to_file(
-    input_file_base_name: &str,
-    config_data: &ConfigData,
-    translation: &str
-) {
-    write_txt_append(
-        TRANSLATION_DIR,
-        &format!("{}_{}2{}.txt", input_file_base_name, config_data.source_lang, config_data.target_lang),
-        translation,
-    ).await.unwrap();
-}
-
-
-async fn write_term_if_needed(term: &Arc<String>, input_file_base_name: &str) {
-    if !term.is_empty() {
-        write_txt_overwrite(TERM_DIR, &format!("{}_term.txt", input_file_base_name), &term).await.unwrap();
-    }
-}
-
-async fn update_config_data(config_data: &ConfigData, file_name: &str, history_lines: usize) {
-    let new_config_data = ConfigData {
-        target_lang: config_data.target_lang.clone(),
-        source_lang: config_data.source_lang.clone(),
-        history_lines,
-    };
-    write_json_overwrite(CONFIG_DIR, &format!("{}.json", file_name), &new_config_data).await.unwrap();
-}
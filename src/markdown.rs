@@ -0,0 +1,249 @@
+use crate::document::{placeholder, Document, Unit};
+use pulldown_cmark::{Event, LinkType, Options, Parser, Tag, TagEnd};
+use std::collections::HashMap;
+use std::ops::Range;
+
+// Markdown 文档: 只翻译段落、标题、表格等处的文字和链接标题, 其余内容按原文写回
+pub struct Markdown {
+    source: String,
+    replacements: Vec<Replacement>,
+}
+
+// 译文替换原文中的一段, 按位置排列
+struct Replacement {
+    range: Range<usize>,
+    unit: Unit,
+    style: Style,
+}
+
+// 前置元数据中的值写回时需要按原来的引号转义
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Prose,
+    Plain,
+    Double,
+    Single,
+}
+
+// 一段连续的行内内容, prose 是其中需要翻译的文字, 其余部分替换为占位符
+struct Run {
+    range: Range<usize>,
+    prose: Vec<Range<usize>>,
+}
+
+pub fn parse(source: &str, front_matter: &[String]) -> Markdown {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
+
+    let mut runs: Vec<Run> = Vec::new();
+    let mut current: Option<Run> = None;
+    let mut skipped = 0;
+    let mut metadata = false;
+    let mut autolink = false;
+    let mut fields = Vec::new();
+
+    for (event, range) in Parser::new_ext(source, options).into_offset_iter() {
+        match event {
+            // 代码块、HTML 块和前置元数据不翻译
+            Event::Start(Tag::CodeBlock(_) | Tag::HtmlBlock | Tag::MetadataBlock(_)) => {
+                runs.extend(current.take());
+                metadata = matches!(event, Event::Start(Tag::MetadataBlock(_)));
+                skipped += 1;
+            }
+            Event::End(TagEnd::CodeBlock | TagEnd::HtmlBlock | TagEnd::MetadataBlock(_)) => {
+                metadata = false;
+                skipped -= 1;
+            }
+            Event::Text(_) if metadata => fields.extend(front_matter_fields(source, range, front_matter)),
+            _ if skipped > 0 => {}
+            // <https://...> 中的文字就是地址, 不翻译
+            Event::Start(Tag::Link { link_type: LinkType::Autolink | LinkType::Email, .. }) => {
+                autolink = true;
+                extend(&mut current, range, None);
+            }
+            Event::Text(_) => extend(&mut current, range.clone(), (!autolink).then_some(range)),
+            Event::Start(Tag::Link { title, .. } | Tag::Image { title, .. }) => {
+                // 标题在链接的末尾, 例如 [文字](url "标题")
+                let title = (!title.is_empty())
+                    .then(|| source[range.clone()].rfind(title.as_ref()).map(|i| range.start + i..range.start + i + title.len()))
+                    .flatten();
+                extend(&mut current, range, title);
+            }
+            Event::Code(_)
+            | Event::InlineMath(_)
+            | Event::InlineHtml(_)
+            | Event::FootnoteReference(_)
+            | Event::SoftBreak
+            | Event::HardBreak
+            | Event::Start(Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Superscript | Tag::Subscript)
+            | Event::End(TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough | TagEnd::Superscript | TagEnd::Subscript)
+            | Event::End(TagEnd::Link | TagEnd::Image) => {
+                autolink = false;
+                extend(&mut current, range, None);
+            }
+            _ => runs.extend(current.take()),
+        }
+    }
+    runs.extend(current);
+
+    let mut replacements = fields;
+    for (i, run) in runs.into_iter().enumerate() {
+        let (text, tags) = mask_run(source, &run);
+        replacements.push(Replacement {
+            range: run.range,
+            unit: Unit { id: (i + 1).to_string(), text, tags },
+            style: Style::Prose,
+        });
+    }
+    replacements.sort_by_key(|replacement| replacement.range.start);
+    Markdown { source: source.to_string(), replacements }
+}
+
+fn extend(current: &mut Option<Run>, range: Range<usize>, prose: Option<Range<usize>>) {
+    let run = current.get_or_insert_with(|| Run { range: range.clone(), prose: Vec::new() });
+    run.range.start = run.range.start.min(range.start);
+    run.range.end = run.range.end.max(range.end);
+    run.prose.extend(prose);
+}
+
+// 文字之外的部分 (强调符号、行内代码、链接地址、引用的 > 等) 替换为占位符
+fn mask_run(source: &str, run: &Run) -> (String, Vec<String>) {
+    let mut prose = run.prose.clone();
+    prose.sort_by_key(|range| range.start);
+
+    let mut text = String::new();
+    let mut tags = Vec::new();
+    let mut position = run.range.start;
+    for range in prose {
+        if range.start < position {
+            continue;
+        }
+        if range.start > position {
+            text.push_str(&placeholder(tags.len()));
+            tags.push(source[position..range.start].to_string());
+        }
+        text.push_str(&source[range.clone()]);
+        position = range.end;
+    }
+    if position < run.range.end {
+        text.push_str(&placeholder(tags.len()));
+        tags.push(source[position..run.range.end].to_string());
+    }
+    (text, tags)
+}
+
+// 前置元数据中指定的顶层字段, 只处理写在同一行的字符串值
+fn front_matter_fields(source: &str, content: Range<usize>, keys: &[String]) -> Vec<Replacement> {
+    let mut fields = Vec::new();
+    let mut offset = content.start;
+    for line in source[content].split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if !keys.iter().any(|wanted| wanted == key) {
+            continue;
+        }
+
+        let value_start = start + key.len() + 1 + (value.len() - value.trim_start().len());
+        let value = value.trim();
+        let (style, range) = match value.chars().next() {
+            Some('"') if value.len() > 1 && value.ends_with('"') => (Style::Double, value_start + 1..value_start + value.len() - 1),
+            Some('\'') if value.len() > 1 && value.ends_with('\'') => (Style::Single, value_start + 1..value_start + value.len() - 1),
+            Some('|' | '>' | '[' | '{' | '&' | '*' | '#') | None => continue,
+            Some(_) => (Style::Plain, value_start..value_start + value.len()),
+        };
+        fields.push(Replacement {
+            unit: Unit::new(format!("front_matter.{}", key), source[range.clone()].to_string()),
+            range,
+            style,
+        });
+    }
+    fields
+}
+
+impl Document for Markdown {
+    fn units(&self) -> Vec<Unit> {
+        self.replacements.iter()
+            .map(|replacement| {
+                let unit = &replacement.unit;
+                Unit { id: unit.id.clone(), text: unit.text.clone(), tags: unit.tags.clone() }
+            })
+            .collect()
+    }
+
+    fn render(&self, translations: &HashMap<String, String>) -> String {
+        let mut output = String::new();
+        let mut position = 0;
+        for replacement in &self.replacements {
+            output.push_str(&self.source[position..replacement.range.start]);
+            match translations.get(&replacement.unit.id) {
+                Some(translation) => output.push_str(&quote(translation, replacement.style)),
+                None => output.push_str(&self.source[replacement.range.clone()]),
+            }
+            position = replacement.range.end;
+        }
+        output.push_str(&self.source[position..]);
+        output
+    }
+
+    // 换行会把段落拆开或结束引用, 合并为一行
+    fn escape(&self, translation: &str) -> String {
+        translation.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn quote(translation: &str, style: Style) -> String {
+    match style {
+        Style::Prose => translation.to_string(),
+        Style::Double => translation.replace('\\', "\\\\").replace('"', "\\\""),
+        Style::Single => translation.replace('\'', "''"),
+        // 译文中有 YAML 的特殊字符时加上双引号
+        Style::Plain if translation.contains([':', '#', '"', '\'']) || translation.starts_with(['-', '?', '!', '%', '@', '`']) => {
+            format!("\"{}\"", translation.replace('\\', "\\\\").replace('"', "\\\""))
+        }
+        Style::Plain => translation.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "---\ntitle: 标题\ndraft: true\n---\n\n# 第一章\n\n这是**重要**的一段，\n包含 `code` 和[链接](https://example.com \"说明\")。\n\n> 引用第一行\n> 第二行\n\n```rust\nfn main() {}\n```\n\n| 列 | 值 |\n| --- | --- |\n| 甲 | <https://example.com> |\n";
+
+    #[test]
+    fn masks_markup_and_skips_code() {
+        let units = parse(SOURCE, &["title".to_string()]).units();
+        let texts: Vec<&str> = units.iter().map(|unit| unit.text.as_str()).collect();
+        assert_eq!(texts[0], "标题");
+        assert_eq!(units[0].id, "front_matter.title");
+        assert!(texts.contains(&"这是[[0]]重要[[1]]的一段，[[2]]包含 [[3]] 和[[4]]链接[[5]]说明[[6]]。"));
+        assert!(!texts.iter().any(|text| text.contains("fn main") || text.contains("example.com") || text.contains("true")));
+    }
+
+    #[test]
+    fn keeps_code_spans_verbatim() {
+        let markdown = parse("运行 ``a `b` c`` 或 `x*y*`。\n", &[]);
+        let units = markdown.units();
+        assert_eq!(units[0].text, "运行 [[0]] 或 [[1]]。");
+        assert_eq!(units[0].tags, vec!["``a `b` c``", "`x*y*`"]);
+
+        let translations = markdown.restore_tags(&HashMap::from([("1".to_string(), "Run [[1]] or [[0]].".to_string())]));
+        assert_eq!(markdown.render(&translations), "Run `x*y*` or ``a `b` c``.\n");
+    }
+
+    #[test]
+    fn quotes_front_matter_translations() {
+        let markdown = parse("---\ntitle: 标题\nsubtitle: \"副\"\n---\n正文\n", &["title".to_string(), "subtitle".to_string()]);
+        let translations = HashMap::from([
+            ("front_matter.title".to_string(), "Part: One".to_string()),
+            ("front_matter.subtitle".to_string(), "say \"hi\"".to_string()),
+        ]);
+        assert_eq!(markdown.render(&translations), "---\ntitle: \"Part: One\"\nsubtitle: \"say \\\"hi\\\"\"\n---\n正文\n");
+    }
+}
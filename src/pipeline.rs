@@ -0,0 +1,706 @@
+use crate::align::{AlignOptions, Aligner, Repair};
+use crate::api::{self, run_workflow, Input, RequestData, WorkflowResult};
+use crate::chapters::{write_chapter_files, ChapterMatcher};
+use crate::chunking::{ChunkMode, ChunkStrategy, ChunkingOptions};
+use crate::config::{APIConfig, ConfigData};
+use crate::context::{ContextWindow, TranslationContext};
+use crate::document::{translate_documents, DocumentOptions, Format};
+use crate::encoding::OutputEncoding;
+use crate::file_operations::{write_bytes_append, write_txt_overwrite, Chunk, LazyFileReader, TERM_DIR};
+use crate::line_ending::LineEndings;
+use crate::progress::{Progress, WorkerStatus};
+use crate::shutdown::{self, Shutdown};
+use crate::state::{ChunkRecord, ChunkStatus, JobState, Usage};
+use encoding_rs::{Encoding, UTF_8};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{mpsc, watch, Mutex, Semaphore};
+
+enum TaskMessage {
+    Started {
+        job_id: usize,
+        index: usize,
+        start_line: usize,
+        end_line: usize,
+        start_byte: u64,
+        end_byte: u64,
+        hash: String,
+        heading: bool,
+        text: Option<String>,
+    },
+    Finished {
+        job_id: usize,
+        index: usize,
+        result: Result<WorkflowResult, String>,
+        repair: Option<Repair>,
+    },
+}
+
+// 一个源文件在一个语言对下的翻译任务
+pub struct Job {
+    pub name: String,
+    pub source_path: String,
+    pub config_data: ConfigData,
+    pub state_file_name: String,
+    pub legacy_config_path: Option<PathBuf>,
+    pub term: Arc<String>,
+    pub term_file_name: Option<String>,
+    pub output_dir: String,
+    pub output_file_name: String,
+    pub source_encoding: &'static Encoding,
+    pub output_encoding: OutputEncoding,
+    pub format: Format,
+}
+
+// 同名的字幕等文件与纯文本的进度格式不同, 文件名中加上扩展名
+pub fn state_file_name(name: &str, config_data: &ConfigData, format: Format) -> String {
+    match format {
+        Format::Text => format!("{}_{}2{}.json", name, config_data.source_lang, config_data.target_lang),
+        format => format!("{}_{}2{}.{}.json", name, config_data.source_lang, config_data.target_lang, format.extension()),
+    }
+}
+
+impl Job {
+    pub fn output_path(&self) -> String {
+        format!("{}/{}", self.output_dir, self.output_file_name)
+    }
+
+    pub fn load_state(&self) -> Result<JobState, String> {
+        let state = JobState::load(&self.state_file_name)
+            .map_err(|e| format!("无法读取任务状态 {}: {}", self.state_file_name, e))?;
+
+        Ok(state.unwrap_or_else(|| {
+            let mut state = JobState::new(
+                &self.source_path,
+                &self.config_data.source_lang,
+                &self.config_data.target_lang,
+                &self.output_path(),
+            );
+            if let Some(path) = &self.legacy_config_path {
+                let output_len = std::fs::metadata(self.output_path()).map(|m| m.len()).unwrap_or(0);
+                state.migrate_legacy(path, output_len);
+            }
+            state
+        }))
+    }
+}
+
+// reorder_window 限制已分发但尚未按顺序写入的块数, 超出时工作流等待
+// shutdown_grace_secs 是收到中断信号后等待翻译中的块完成的时间
+// context_lines 大于0时把前面若干行原文和译文作为上文发送, 同一文件的块需要依次等待前一块写入
+// alignment 不为空时检查译文与原文的行数是否一致, 不一致时修复
+// line_endings 决定译文的换行符, 译文写入时不再另外添加换行符
+pub struct PipelineOptions {
+    pub task_num: usize,
+    pub chunking: ChunkingOptions,
+    pub output_key: String,
+    pub reconcile: bool,
+    pub reorder_window: usize,
+    pub shutdown_grace_secs: u64,
+    pub context_lines: usize,
+    pub alignment: Option<AlignOptions>,
+    pub line_endings: LineEndings,
+    pub chapter_files: bool,
+    pub documents: DocumentOptions,
+}
+
+pub const DEFAULT_SHUTDOWN_GRACE_SECS: u64 = 30;
+
+pub fn default_reorder_window(task_num: usize) -> usize {
+    task_num * 4
+}
+
+#[derive(Serialize)]
+pub struct JobReport {
+    pub name: String,
+    pub source_lang: String,
+    pub target_lang: String,
+    pub output: String,
+    pub chunks_done: usize,
+    pub chunks_failed: usize,
+    pub total_tokens: u64,
+    pub elapsed_secs: f64,
+    pub complete: bool,
+}
+
+pub struct RunSummary {
+    pub reports: Vec<JobReport>,
+    pub interrupted: bool,
+}
+
+impl RunSummary {
+    // 0: 全部完成, 2: 被中断, 3: 已结束但有失败的块
+    pub fn exit_code(&self) -> i32 {
+        if self.interrupted {
+            2
+        } else if self.reports.iter().any(|report| !report.complete) {
+            3
+        } else {
+            0
+        }
+    }
+}
+
+struct JobCursor {
+    strategy: ChunkStrategy,
+    start_line: usize,
+    start_byte: u64,
+    first_index: usize,
+    keep_blank_tail: bool,
+    chapters: Option<Arc<ChapterMatcher>>,
+}
+
+// 所有任务共享一个读取队列, 当前文件读完后切换到下一个文件
+struct JobQueue {
+    jobs: Arc<Vec<Job>>,
+    progress: Arc<Progress>,
+    cursors: Vec<JobCursor>,
+    current: usize,
+    reader: Option<LazyFileReader>,
+    // 打开或读取出错的任务, 出错位置之后的内容没有翻译
+    read_errors: Vec<Option<String>>,
+}
+
+impl JobQueue {
+    async fn next_chunk(&mut self) -> Option<(usize, usize, Chunk)> {
+        while self.current < self.jobs.len() {
+            if self.reader.is_none() {
+                let job = &self.jobs[self.current];
+                let cursor = &self.cursors[self.current];
+                let reader = LazyFileReader::new(
+                    &job.source_path,
+                    job.source_encoding,
+                    cursor.strategy.clone(),
+                    cursor.start_byte,
+                    cursor.start_line,
+                    cursor.keep_blank_tail,
+                    cursor.chapters.clone()
+                ).await;
+                match reader {
+                    Ok(reader) => self.reader = Some(reader),
+                    Err(e) => {
+                        let message = format!("无法打开文件 {}: {}", job.source_path, e);
+                        self.progress.log(&message);
+                        self.read_errors[self.current] = Some(message);
+                        self.current += 1;
+                        continue;
+                    }
+                }
+            }
+
+            let reader = self.reader.as_mut().unwrap();
+            match reader.read_next_chunk().await {
+                Ok(Some(chunk)) => {
+                    let index = self.cursors[self.current].first_index + reader.get_call_count() - 1;
+                    return Some((self.current, index, chunk));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    let message = format!("读取文件 {} 出错: {}", self.jobs[self.current].source_path, e);
+                    self.progress.log(&message);
+                    self.read_errors[self.current] = Some(message);
+                }
+            }
+
+            self.reader = None;
+            self.current += 1;
+        }
+
+        None
+    }
+}
+
+struct JobProgress {
+    state: JobState,
+    next_index: usize,
+    pending: BTreeMap<usize, (Result<WorkflowResult, String>, Option<Repair>)>,
+    texts: HashMap<usize, String>,
+    done: usize,
+    failed: usize,
+    total_tokens: u64,
+    started: Instant,
+    elapsed_secs: f64,
+}
+
+// 纯文本逐块追加写入, 字幕等结构化文件先解析再按段翻译
+pub async fn run_jobs(jobs: Vec<Job>, api_config: Arc<APIConfig>, options: PipelineOptions) -> Result<RunSummary, String> {
+    let (documents, texts): (Vec<Job>, Vec<Job>) = jobs.into_iter().partition(|job| job.format != Format::Text);
+    let shutdown = Shutdown::new();
+    let listener = shutdown::listen(Arc::clone(&shutdown));
+
+    let mut summary = RunSummary { reports: Vec::new(), interrupted: false };
+    if !texts.is_empty() {
+        summary = run_text_jobs(texts, Arc::clone(&api_config), &options, &shutdown).await?;
+    }
+    if !documents.is_empty() && !summary.interrupted {
+        let documents = translate_documents(documents, api_config, &options, &shutdown).await?;
+        summary.reports.extend(documents.reports);
+        summary.interrupted = documents.interrupted;
+    }
+    listener.abort();
+    Ok(summary)
+}
+
+async fn run_text_jobs(
+    jobs: Vec<Job>,
+    api_config: Arc<APIConfig>,
+    options: &PipelineOptions,
+    shutdown: &Arc<Shutdown>
+) -> Result<RunSummary, String> {
+    // 按句子切分的块会在行中间断开, 无法逐行对齐
+    if options.alignment.is_some() && options.chunking.mode == ChunkMode::Sentences {
+        return Err("对齐模式不能与按句子切分同时使用".to_string());
+    }
+
+    let mut progress = Vec::new();
+    for job in &jobs {
+        let mut state = job.load_state()?;
+        state.discard_in_flight();
+        state.verify_resume(&job.source_path, &job.output_path(), options.reconcile)?;
+        state.check_output_encoding(&job.output_encoding.label()).map_err(|e| format!("{}: {}", job.name, e))?;
+        if job.source_encoding != UTF_8 {
+            println!("{} 的编码为 {}", job.name, job.source_encoding.name());
+        }
+        state.save(&job.state_file_name).await.map_err(|e| format!("无法保存任务状态: {}", e))?;
+        progress.push(JobProgress {
+            next_index: state.next_index(),
+            pending: BTreeMap::new(),
+            texts: HashMap::new(),
+            state,
+            done: 0,
+            failed: 0,
+            total_tokens: 0,
+            started: Instant::now(),
+            elapsed_secs: 0.0,
+        });
+    }
+
+    let mut cursors = Vec::new();
+    for (job, p) in jobs.iter().zip(&progress) {
+        cursors.push(JobCursor {
+            strategy: options.chunking.strategy_for(&job.term).map_err(|e| format!("{}: {}", job.name, e))?,
+            start_line: p.state.next_line(),
+            start_byte: p.state.next_byte(),
+            first_index: p.next_index,
+            keep_blank_tail: options.alignment.is_some_and(|alignment| alignment.preserve_layout),
+            chapters: options.chunking.chapters.clone(),
+        });
+    }
+
+    // 预先数出剩余的块数, 用于显示进度和预计剩余时间
+    let committed: usize = progress.iter()
+        .map(|p| p.state.count(ChunkStatus::Done) + p.state.count(ChunkStatus::Failed))
+        .sum();
+    let mut remaining = 0;
+    for (job, cursor) in jobs.iter().zip(&cursors) {
+        remaining += count_remaining_chunks(job, cursor).await;
+    }
+    let display = Progress::new(options.task_num, committed + remaining, committed, api::is_verbose());
+
+    let jobs = Arc::new(jobs);
+    let (tx, rx) = mpsc::channel::<TaskMessage>(1024);
+    let window = Arc::new(Semaphore::new(options.reorder_window.max(1)));
+
+    let mut contexts = Vec::new();
+    for (job, p) in jobs.iter().zip(&progress) {
+        contexts.push(watch::Sender::new(ContextWindow::load(job, &p.state, options.context_lines).await));
+    }
+
+    let dispatch = Arc::new(Dispatch {
+        api_config,
+        jobs: Arc::clone(&jobs),
+        queue: Mutex::new(JobQueue {
+            jobs: Arc::clone(&jobs),
+            progress: Arc::clone(&display),
+            read_errors: vec![None; cursors.len()],
+            cursors,
+            current: 0,
+            reader: None,
+        }),
+        window: Arc::clone(&window),
+        shutdown: Arc::clone(shutdown),
+        display: Arc::clone(&display),
+        contexts,
+        context_lines: options.context_lines,
+        output_key: options.output_key.clone(),
+        alignment: options.alignment,
+        line_endings: options.line_endings,
+    });
+
+    let handles = spawn_translation_tasks(options.task_num, Arc::clone(&dispatch), tx);
+    let aborts = handles.iter().map(|handle| handle.abort_handle()).collect();
+    let log = Arc::clone(&display);
+    let grace = shutdown::spawn_grace_timer(shutdown, &window, aborts, options.shutdown_grace_secs, "块", move |message| log.log(message));
+    let renderer = display.spawn_renderer();
+
+    let processed = process_results(rx, &window, options, &jobs, &mut progress, &dispatch).await;
+
+    grace.abort();
+    // 写入失败时不再等待翻译中的块, 任务状态保持在最后一次成功保存时
+    if processed.is_err() {
+        for handle in &handles {
+            handle.abort();
+        }
+    }
+    for handle in handles {
+        let _ = handle.await;
+    }
+    renderer.abort();
+    display.finish();
+    processed?;
+
+    let interrupted = shutdown.is_stopping();
+    let read_errors = std::mem::take(&mut dispatch.queue.lock().await.read_errors);
+    let mut reports = Vec::new();
+    for ((job, mut progress), read_error) in jobs.iter().zip(progress).zip(read_errors) {
+        if options.chapter_files {
+            let count = write_chapter_files(job, &mut progress.state).await.map_err(|e| format!("{}: {}", job.name, e))?;
+            println!("{} 已拆分为 {} 个章节文件", job.name, count);
+        }
+        // 中断时翻译中的块也写入状态, 下次运行时会丢弃并重新翻译
+        progress.state.save(&job.state_file_name).await.map_err(|e| format!("无法保存任务状态: {}", e))?;
+        if let Some(error) = &read_error {
+            println!("{} 未完成: {}", job.name, error);
+        }
+        reports.push(JobReport {
+            name: job.name.clone(),
+            source_lang: job.config_data.source_lang.clone(),
+            target_lang: job.config_data.target_lang.clone(),
+            output: job.output_path(),
+            chunks_done: progress.done,
+            chunks_failed: progress.failed,
+            total_tokens: progress.total_tokens,
+            elapsed_secs: progress.elapsed_secs,
+            complete: !interrupted && read_error.is_none() && progress.state.count(ChunkStatus::Failed) == 0,
+        });
+    }
+
+    if interrupted {
+        println!("翻译已中断, 进度已保存, 再次运行即可继续");
+    }
+    Ok(RunSummary { reports, interrupted })
+}
+
+// 所有工作流共享的状态
+struct Dispatch {
+    api_config: Arc<APIConfig>,
+    jobs: Arc<Vec<Job>>,
+    queue: Mutex<JobQueue>,
+    window: Arc<Semaphore>,
+    shutdown: Arc<Shutdown>,
+    display: Arc<Progress>,
+    contexts: Vec<watch::Sender<ContextWindow>>,
+    context_lines: usize,
+    output_key: String,
+    alignment: Option<AlignOptions>,
+    line_endings: LineEndings,
+}
+
+fn spawn_translation_tasks(
+    task_num: usize,
+    dispatch: Arc<Dispatch>,
+    tx: Sender<TaskMessage>
+) -> Vec<tokio::task::JoinHandle<()>> {
+    let mut handles = Vec::new();
+
+    for i in 0..task_num {
+        let handle = tokio::spawn(create_task(i, Arc::clone(&dispatch), tx.clone()));
+        handles.push(handle);
+    }
+
+    handles
+}
+
+async fn create_task(task_id: usize, dispatch: Arc<Dispatch>, tx: Sender<TaskMessage>) {
+    let display = &dispatch.display;
+    loop {
+        // 许可在该块按顺序写入输出后由 process_results 归还, 中断时窗口关闭
+        display.set_worker(task_id, WorkerStatus::Waiting);
+        match dispatch.window.acquire().await {
+            Ok(permit) => permit.forget(),
+            Err(_) => break,
+        }
+        if dispatch.shutdown.is_stopping() {
+            break;
+        }
+
+        display.set_worker(task_id, WorkerStatus::Reading);
+        let next = dispatch.queue.lock().await.next_chunk().await;
+
+        if let Some((job_id, index, chunk)) = next {
+            let job = &dispatch.jobs[job_id];
+            let sent = tx.send(TaskMessage::Started {
+                job_id,
+                index,
+                start_line: chunk.start_line,
+                end_line: chunk.end_line,
+                start_byte: chunk.start_byte,
+                end_byte: chunk.end_byte,
+                hash: chunk.hash,
+                heading: chunk.heading,
+                text: (dispatch.context_lines > 0).then(|| chunk.text.clone()),
+            }).await;
+            // 写入出错时接收端已经关闭
+            if sent.is_err() {
+                break;
+            }
+
+            // 上文只取已经按顺序写入的译文, 需要等前一块写入后才能发送
+            let context = if dispatch.context_lines > 0 {
+                display.set_worker(task_id, WorkerStatus::WaitingContext { job: job.name.clone(), index });
+                let mut committed = dispatch.contexts[job_id].subscribe();
+                let context = committed.wait_for(|context| context.next_index >= index).await.map(|context| context.snapshot());
+                match context {
+                    Ok(context) => context,
+                    Err(_) => break,
+                }
+            } else {
+                None
+            };
+
+            display.set_worker(task_id, WorkerStatus::Translating {
+                job: job.name.clone(),
+                index,
+                start_line: chunk.start_line,
+                end_line: chunk.end_line,
+                since: Instant::now(),
+            });
+            let (result, repair) = match dispatch.alignment {
+                Some(options) => {
+                    // 按原始文本分行, 开头被跳过的空行也要在译文中保留, 才能与原文逐行对应
+                    let lines: Vec<String> = chunk.raw.split_inclusive('\n').map(str::to_string).collect();
+                    let aligner = Aligner { job, api_config: &dispatch.api_config, output_key: &dispatch.output_key, options };
+                    match aligner.translate(&lines, context.as_ref()).await {
+                        Ok((result, repair)) => (Ok(result), repair),
+                        Err(e) => (Err(e), None),
+                    }
+                }
+                None => {
+                    let result = process_task(&job.config_data, &dispatch.api_config, &job.term, chunk.text, context.as_ref()).await;
+                    (format_translation(result, &dispatch.output_key, dispatch.line_endings, &chunk.raw), None)
+                }
+            };
+            if tx.send(TaskMessage::Finished { job_id, index, result, repair }).await.is_err() {
+                break;
+            }
+        } else {
+            break;
+        }
+    }
+    display.set_worker(task_id, WorkerStatus::Finished);
+}
+
+async fn count_remaining_chunks(job: &Job, cursor: &JobCursor) -> usize {
+    let reader = LazyFileReader::new(
+        &job.source_path,
+        job.source_encoding,
+        cursor.strategy.clone(),
+        cursor.start_byte,
+        cursor.start_line,
+        cursor.keep_blank_tail,
+        cursor.chapters.clone()
+    ).await;
+    let Ok(mut reader) = reader else {
+        return 0;
+    };
+
+    let mut count = 0;
+    while let Ok(Some(_)) = reader.read_next_chunk().await {
+        count += 1;
+    }
+    count
+}
+
+pub async fn process_task(
+    config_data: &ConfigData,
+    api_config: &APIConfig,
+    term: &str,
+    value: String,
+    context: Option<&TranslationContext>
+) -> Result<WorkflowResult, String> {
+    let user_id = "fww";
+    let response_mode = "streaming";
+    let mut input = Input::new(&config_data.target_lang, value, &config_data.source_lang, term);
+    if let Some(context) = context {
+        input = input.with_context(&context.source, &context.translation);
+    }
+    let request_data = RequestData::new(input, response_mode, user_id);
+    let result = run_workflow(&api_config.api_key, &api_config.base_url, &request_data).await;
+
+    match result {
+        Ok(Some(outputs)) => Ok(outputs),
+        Ok(None) => Err("返回结果为空".to_string()),
+        Err(err) => Err(format!("请求出错: {}", err)),
+    }
+}
+
+// 所有工作流结束后发送端全部释放, recv 返回 None
+// 写入输出或保存状态失败时关闭分发窗口并返回错误
+async fn process_results(
+    mut rx: mpsc::Receiver<TaskMessage>,
+    window: &Semaphore,
+    options: &PipelineOptions,
+    jobs: &[Job],
+    progress: &mut [JobProgress],
+    dispatch: &Dispatch
+) -> Result<(), String> {
+    let display = dispatch.display.as_ref();
+    while let Some(message) = rx.recv().await {
+        match message {
+            TaskMessage::Started { job_id, index, start_line, end_line, start_byte, end_byte, hash, heading, text } => {
+                if let Some(text) = text {
+                    progress[job_id].texts.insert(index, text);
+                }
+                let state = &mut progress[job_id].state;
+                let position = state.chunks.partition_point(|chunk| chunk.index < index);
+                state.chunks.insert(position, ChunkRecord {
+                    index,
+                    start_line,
+                    end_line,
+                    start_byte,
+                    end_byte,
+                    hash,
+                    status: ChunkStatus::InFlight,
+                    attempts: 1,
+                    output_offset: 0,
+                    output_len: 0,
+                    usage: None,
+                    error: None,
+                    repair: None,
+                    heading,
+                });
+            }
+            TaskMessage::Finished { job_id, index, result, repair } => {
+                let job_progress = &mut progress[job_id];
+                job_progress.pending.insert(index, (result, repair));
+
+                // 连续的结果到齐后立即按顺序写入
+                while let Some((result, repair)) = job_progress.pending.remove(&job_progress.next_index) {
+                    let index = job_progress.next_index;
+                    job_progress.state.chunk_mut(index).unwrap().repair = repair;
+                    let translation = match process_normal_result(&jobs[job_id], job_progress, index, result, options, display).await {
+                        Ok(translation) => translation,
+                        Err(e) => {
+                            window.close();
+                            return Err(format!("{}: {}", jobs[job_id].name, e));
+                        }
+                    };
+                    let source = job_progress.texts.remove(&index).unwrap_or_default();
+                    dispatch.contexts[job_id].send_modify(|context| context.commit(&source, translation.as_deref()));
+                    job_progress.next_index += 1;
+                    job_progress.elapsed_secs = job_progress.started.elapsed().as_secs_f64();
+                    window.add_permits(1);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn process_normal_result(
+    job: &Job,
+    progress: &mut JobProgress,
+    index: usize,
+    result: Result<WorkflowResult, String>,
+    options: &PipelineOptions,
+    display: &Progress
+) -> Result<Option<String>, String> {
+    let output_offset = progress.state.output_len();
+    let translation = extract_translation(result, &options.output_key);
+
+    // 先追加并同步输出文件, 再保存记录; 两者之间崩溃时多出的内容在下次启动时截断
+    // 返回写入的译文, 翻译失败时返回 None; 写入出错时直接返回错误, 不保存这一块的记录
+    let committed = match translation {
+        Ok((translation, result)) => {
+            let (output_end, unmappable) = write_translation_to_file(job, &translation, output_offset).await?;
+            write_term_if_needed(job).await?;
+            if unmappable {
+                display.log(&format!(
+                    "{} chunk {} 中有无法用 {} 表示的字符, 已替换为数字字符引用",
+                    job.name, index, job.output_encoding.encoding.name()
+                ));
+            }
+            let record = progress.state.chunk_mut(index).unwrap();
+            record.status = ChunkStatus::Done;
+            record.output_offset = output_offset;
+            record.output_len = output_end - output_offset;
+            record.usage = Some(Usage {
+                total_tokens: result.total_tokens,
+                elapsed_time: result.elapsed_time,
+            });
+            progress.done += 1;
+            progress.total_tokens += result.total_tokens;
+            display.chunk_committed(record.end_line - record.start_line, result.total_tokens, false);
+            display.log(&format!("{} chunk {} 已返回结果", job.name, index));
+            Some(translation)
+        }
+        Err(e) => {
+            // 在输出中占位, 之后可以用 retry-failed 替换为译文
+            let marker = failed_marker(index);
+            let (output_end, _) = write_translation_to_file(job, &format!("{}\n", marker), output_offset).await?;
+            let record = progress.state.chunk_mut(index).unwrap();
+            record.status = ChunkStatus::Failed;
+            record.output_offset = output_offset;
+            record.output_len = output_end - output_offset;
+            display.chunk_committed(record.end_line - record.start_line, 0, true);
+            display.log(&format!("{} chunk {} 未返回结果: {}", job.name, index, e));
+            record.error = Some(e);
+            progress.failed += 1;
+            None
+        }
+    };
+
+    progress.state.save(&job.state_file_name).await.map_err(|e| format!("无法保存任务状态: {}", e))?;
+    Ok(committed)
+}
+
+pub fn failed_marker(index: usize) -> String {
+    format!("[[未翻译: chunk {}]]", index)
+}
+
+// 不对齐时按原文整理译文的换行符
+pub fn format_translation(
+    result: Result<WorkflowResult, String>,
+    output_key: &str,
+    line_endings: LineEndings,
+    source: &str
+) -> Result<WorkflowResult, String> {
+    let mut result = result?;
+    if let Some(Value::String(text)) = result.outputs.get_mut(output_key) {
+        *text = line_endings.format(text, source);
+    }
+    Ok(result)
+}
+
+pub fn extract_translation(result: Result<WorkflowResult, String>, output_key: &str) -> Result<(String, WorkflowResult), String> {
+    let result = result?;
+    let translation = result.outputs.get(output_key)
+        .and_then(|value| value.as_str())
+        .ok_or_else(|| format!("输出中没有{}", output_key))?;
+    Ok((translation.to_string(), result))
+}
+
+// 译文已经带有换行符, 按输出编码写入, 返回新的长度和是否有无法用输出编码表示的字符
+async fn write_translation_to_file(job: &Job, translation: &str, committed_len: u64) -> Result<(u64, bool), String> {
+    let (bytes, unmappable) = job.output_encoding.encode(translation, committed_len == 0);
+    let output_end = write_bytes_append(&job.output_dir, &job.output_file_name, &bytes, committed_len)
+        .await
+        .map_err(|e| format!("无法写入输出文件 {}: {}", job.output_path(), e))?;
+    Ok((output_end, unmappable))
+}
+
+async fn write_term_if_needed(job: &Job) -> Result<(), String> {
+    if let Some(term_file_name) = &job.term_file_name {
+        if !job.term.is_empty() {
+            write_txt_overwrite(TERM_DIR, term_file_name, &job.term)
+                .await
+                .map_err(|e| format!("无法写入术语文件 {}: {}", term_file_name, e))?;
+        }
+    }
+    Ok(())
+}
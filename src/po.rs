@@ -0,0 +1,628 @@
+use crate::document::{mask, Document, DocumentOptions, Unit};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+// printf 格式 (%s、%1$d、%(name)s)、{0}/{name} 占位符和 HTML 标签, 翻译时替换为占位符
+static FORMAT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"%(?:\d+\$)?[-+ #0]*\d*(?:\.\d+)?(?:hh|h|ll|l|L|q|j|z|t)?[diouxXeEfFgGaAcspn%]",
+        r"|%\([A-Za-z_][A-Za-z0-9_]*\)[-+ #0]*\d*(?:\.\d+)?[diouxXeEfFgGcrsa]",
+        r"|\{[A-Za-z0-9_.]*\}",
+        r"|</?[A-Za-z][^<>]*>",
+    )).unwrap()
+});
+static NPLURALS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"nplurals\s*=\s*(\d+)").unwrap());
+static PLURAL: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"plural\s*=\s*([^;\n]+)").unwrap());
+static PLURAL_TOKEN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\d+|n|\|\||&&|[=!<>]=|[-+*/%<>!?:()]").unwrap());
+
+// Gettext PO/POT 文件: 把 msgid 翻译后写入 msgstr, 注释和其他字段原样保留
+// samples 是每种复数形式的一个示例 n, 按 plural 规则找不到时为 None, 这一形式不翻译
+pub struct Po {
+    entries: Vec<Entry>,
+    plurals: usize,
+    rule: String,
+    samples: Vec<Option<u64>>,
+    include_fuzzy: bool,
+    mark_fuzzy: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Comment,
+    Flags,
+    // #| 开头的旧 msgid, 重新翻译后不再需要
+    Previous,
+    Context,
+    Id,
+    Plural,
+    Str,
+}
+
+// 一个条目的各行和解码后的字段, 续行属于前一个字段
+struct Entry {
+    lines: Vec<(Field, String)>,
+    context: Option<String>,
+    id: Option<String>,
+    plural: Option<String>,
+    strings: Vec<String>,
+    comments: Vec<String>,
+    fuzzy: bool,
+}
+
+pub fn parse(source: &str, target_lang: &str, options: &DocumentOptions) -> Result<Po, String> {
+    let mut entries = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut start = 0;
+    for (number, line) in source.lines().chain([""]).enumerate() {
+        if line.trim().is_empty() {
+            if !current.is_empty() {
+                entries.push(parse_entry(&current, start)?);
+                current.clear();
+            }
+        } else {
+            if current.is_empty() {
+                start = number;
+            }
+            current.push(line);
+        }
+    }
+    if !entries.iter().any(|entry| entry.id.as_deref().is_some_and(|id| !id.is_empty())) {
+        return Err("没有找到 msgid".to_string());
+    }
+
+    // 文件头中有 Plural-Forms 时按其中的复数形式数量, POT 模板中没有填写时按目标语言补上
+    let mut forms = None;
+    if let Some(header) = entries.iter_mut().find(|entry| entry.is_header()) {
+        forms = header.strings.first()
+            .and_then(|header| {
+                let plurals = NPLURALS.captures(header)?[1].parse().ok().filter(|&plurals| plurals > 0)?;
+                Some((plurals, PLURAL.captures(header)?[1].trim().to_string()))
+            });
+        if forms.is_none() {
+            header.fill_header(target_lang);
+        }
+    }
+    let (plurals, rule) = forms.unwrap_or_else(|| {
+        let (plurals, rule) = plural_forms(target_lang);
+        (plurals, rule.to_string())
+    });
+    let samples = (0..plurals as u64).map(|index| plural_sample(&rule, index)).collect();
+
+    Ok(Po {
+        entries,
+        plurals,
+        rule,
+        samples,
+        include_fuzzy: options.include_fuzzy,
+        mark_fuzzy: options.mark_fuzzy,
+    })
+}
+
+fn parse_entry(lines: &[&str], start: usize) -> Result<Entry, String> {
+    let mut entry = Entry {
+        lines: Vec::new(),
+        context: None,
+        id: None,
+        plural: None,
+        strings: Vec::new(),
+        comments: Vec::new(),
+        fuzzy: false,
+    };
+    let mut field = Field::Comment;
+    for (offset, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        let value = if let Some(comment) = trimmed.strip_prefix('#') {
+            field = match comment.chars().next() {
+                Some(',') => {
+                    entry.fuzzy |= comment[1..].split(',').any(|flag| flag.trim() == "fuzzy");
+                    Field::Flags
+                }
+                Some('|') => Field::Previous,
+                Some('.') => {
+                    entry.comments.push(comment[1..].trim().to_string());
+                    Field::Comment
+                }
+                _ => Field::Comment,
+            };
+            entry.lines.push((field, line.to_string()));
+            continue;
+        } else if trimmed.starts_with('"') {
+            if matches!(field, Field::Comment | Field::Flags | Field::Previous) {
+                return Err(format!("第 {} 行的字符串前缺少关键字", start + offset + 1));
+            }
+            trimmed
+        } else {
+            let (keyword, rest) = trimmed.split_once([' ', '\t']).unwrap_or((trimmed, ""));
+            field = match keyword {
+                "msgctxt" => Field::Context,
+                "msgid" => Field::Id,
+                "msgid_plural" => Field::Plural,
+                keyword if keyword == "msgstr" || keyword.starts_with("msgstr[") => {
+                    entry.strings.push(String::new());
+                    Field::Str
+                }
+                _ => return Err(format!("第 {} 行无法识别: {}", start + offset + 1, trimmed)),
+            };
+            rest.trim()
+        };
+
+        let text = unquote(value).ok_or_else(|| format!("第 {} 行的字符串格式不正确", start + offset + 1))?;
+        let target = match field {
+            Field::Context => entry.context.get_or_insert_with(String::new),
+            Field::Id => entry.id.get_or_insert_with(String::new),
+            Field::Plural => entry.plural.get_or_insert_with(String::new),
+            _ => entry.strings.last_mut().unwrap(),
+        };
+        target.push_str(&text);
+        entry.lines.push((field, line.to_string()));
+    }
+    Ok(entry)
+}
+
+// 去掉引号并还原 \n、\" 等转义
+fn unquote(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => text.push('\n'),
+            't' => text.push('\t'),
+            'r' => text.push('\r'),
+            'a' => text.push('\u{7}'),
+            'b' => text.push('\u{8}'),
+            'f' => text.push('\u{c}'),
+            'v' => text.push('\u{b}'),
+            other => text.push(other),
+        }
+    }
+    Some(text)
+}
+
+fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// 有多行时按 msgmerge 的习惯先写空字符串, 每行一个字符串
+fn format_field(keyword: &str, text: &str) -> Vec<String> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    if lines.len() <= 1 {
+        return vec![format!("{} {}", keyword, quote(text))];
+    }
+    let mut formatted = vec![format!("{} \"\"", keyword)];
+    formatted.extend(lines.iter().map(|line| quote(line)));
+    formatted
+}
+
+// 常见语言的复数形式数量和规则, 其他语言按英语处理
+fn plural_forms(lang: &str) -> (usize, &'static str) {
+    let lang = lang.to_lowercase().replace('-', "_");
+    let language = lang.split('_').next().unwrap_or_default();
+    match language {
+        "zh" | "ja" | "ko" | "vi" | "th" | "id" | "ms" | "lo" | "km" | "my" => (1, "0"),
+        "fr" => (2, "(n > 1)"),
+        "pt" if lang == "pt_br" => (2, "(n > 1)"),
+        "ru" | "uk" | "be" | "sr" | "hr" | "bs" => {
+            (3, "(n%10==1 && n%100!=11 ? 0 : n%10>=2 && n%10<=4 && (n%100<10 || n%100>=20) ? 1 : 2)")
+        }
+        "pl" => (3, "(n==1 ? 0 : n%10>=2 && n%10<=4 && (n%100<10 || n%100>=20) ? 1 : 2)"),
+        "cs" | "sk" => (3, "((n==1) ? 0 : (n>=2 && n<=4) ? 1 : 2)"),
+        "ar" => (6, "(n==0 ? 0 : n==1 ? 1 : n==2 ? 2 : n%100>=3 && n%100<=10 ? 3 : n%100>=11 ? 4 : 5)"),
+        _ => (2, "(n != 1)"),
+    }
+}
+
+// 复数形式 index 的示例 n, 优先使用 1, 其余取最小的 n
+fn plural_sample(rule: &str, index: u64) -> Option<u64> {
+    let matches = |n: u64| plural_index(rule, n) == Some(index);
+    if matches(1) {
+        return Some(1);
+    }
+    (0..=1000).find(|&n| matches(n))
+}
+
+// 计算 plural 规则这个 C 表达式的值, 支持三元运算、逻辑、比较和算术运算
+fn plural_index(rule: &str, n: u64) -> Option<u64> {
+    let tokens: Vec<&str> = PLURAL_TOKEN.find_iter(rule).map(|token| token.as_str()).collect();
+    let mut evaluator = PluralEvaluator { tokens: &tokens, position: 0, n: n as i64 };
+    let value = evaluator.ternary()?;
+    (evaluator.position == tokens.len() && value >= 0).then_some(value as u64)
+}
+
+struct PluralEvaluator<'a> {
+    tokens: &'a [&'a str],
+    position: usize,
+    n: i64,
+}
+
+impl PluralEvaluator<'_> {
+    fn eat(&mut self, token: &str) -> bool {
+        let matched = self.tokens.get(self.position) == Some(&token);
+        self.position += usize::from(matched);
+        matched
+    }
+
+    fn ternary(&mut self) -> Option<i64> {
+        let condition = self.binary(0)?;
+        if !self.eat("?") {
+            return Some(condition);
+        }
+        let then = self.ternary()?;
+        if !self.eat(":") {
+            return None;
+        }
+        let otherwise = self.ternary()?;
+        Some(if condition != 0 { then } else { otherwise })
+    }
+
+    // 按优先级从低到高: || && 相等 比较 加减 乘除
+    fn binary(&mut self, level: usize) -> Option<i64> {
+        const LEVELS: &[&[&str]] = &[&["||"], &["&&"], &["==", "!="], &["<", ">", "<=", ">="], &["+", "-"], &["*", "/", "%"]];
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut value = self.binary(level + 1)?;
+        while let Some(&operator) = self.tokens.get(self.position).filter(|token| LEVELS[level].contains(token)) {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            value = match operator {
+                "||" => i64::from(value != 0 || right != 0),
+                "&&" => i64::from(value != 0 && right != 0),
+                "==" => i64::from(value == right),
+                "!=" => i64::from(value != right),
+                "<" => i64::from(value < right),
+                ">" => i64::from(value > right),
+                "<=" => i64::from(value <= right),
+                ">=" => i64::from(value >= right),
+                "+" => value.checked_add(right)?,
+                "-" => value.checked_sub(right)?,
+                "*" => value.checked_mul(right)?,
+                "/" => value.checked_div(right)?,
+                _ => value.checked_rem(right)?,
+            };
+        }
+        Some(value)
+    }
+
+    fn unary(&mut self) -> Option<i64> {
+        if self.eat("!") {
+            return Some(i64::from(self.unary()? == 0));
+        }
+        if self.eat("(") {
+            let value = self.ternary()?;
+            return self.eat(")").then_some(value);
+        }
+        let token = self.tokens.get(self.position)?;
+        self.position += 1;
+        if *token == "n" {
+            Some(self.n)
+        } else {
+            token.parse().ok()
+        }
+    }
+}
+
+impl Entry {
+    fn is_header(&self) -> bool {
+        self.id.as_deref() == Some("") && self.context.is_none()
+    }
+
+    // 填写 POT 模板文件头中的 Language 和 Plural-Forms
+    fn fill_header(&mut self, target_lang: &str) {
+        let (plurals, rule) = plural_forms(target_lang);
+        let mut fields: Vec<String> = self.strings[0].lines().map(|line| format!("{}\n", line)).collect();
+        for (name, value) in [("Language:", target_lang.to_string()), ("Plural-Forms:", format!("nplurals={}; plural={};", plurals, rule))] {
+            let field = format!("{} {}\n", name, value);
+            match fields.iter_mut().find(|line| line.starts_with(name)) {
+                Some(line) => *line = field,
+                None => fields.push(field),
+            }
+        }
+        self.strings[0] = fields.concat();
+        self.lines.retain(|(field, _)| *field != Field::Str);
+        self.lines.extend(format_field("msgstr", &self.strings[0]).into_iter().map(|line| (Field::Str, line)));
+    }
+
+    // 未翻译的条目, 以及按设置包括的待定条目需要翻译, 文件头和已作废的条目不翻译
+    fn needs_translation(&self, include_fuzzy: bool) -> bool {
+        match &self.id {
+            Some(id) if !id.is_empty() => self.strings.iter().all(String::is_empty) || (include_fuzzy && self.fuzzy),
+            _ => false,
+        }
+    }
+
+    fn render(&self, strings: &[String], mark_fuzzy: bool) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut flagged = false;
+        for (field, line) in &self.lines {
+            match field {
+                Field::Previous | Field::Str => {}
+                Field::Flags => {
+                    let mut flags: Vec<&str> = line.trim()[2..].split(',')
+                        .map(str::trim)
+                        .filter(|flag| !flag.is_empty() && *flag != "fuzzy")
+                        .collect();
+                    if mark_fuzzy && !flagged {
+                        flags.insert(0, "fuzzy");
+                    }
+                    if !flags.is_empty() {
+                        lines.push(format!("#, {}", flags.join(", ")));
+                    }
+                    flagged = true;
+                }
+                _ => {
+                    // 标记写在 msgctxt 和 msgid 之前
+                    if mark_fuzzy && !flagged && *field != Field::Comment {
+                        lines.push("#, fuzzy".to_string());
+                        flagged = true;
+                    }
+                    lines.push(line.clone());
+                }
+            }
+        }
+        if self.plural.is_some() {
+            for (i, string) in strings.iter().enumerate() {
+                lines.extend(format_field(&format!("msgstr[{}]", i), string));
+            }
+        } else {
+            lines.extend(format_field("msgstr", &strings[0]));
+        }
+        lines
+    }
+}
+
+impl Po {
+    // 按条目在文件中的位置编号, 复数条目的每种形式编号加上 .0、.1 等
+    fn translatable(&self) -> impl Iterator<Item = (usize, &Entry)> {
+        self.entries.iter()
+            .enumerate()
+            .map(|(i, entry)| (i + 1, entry))
+            .filter(|(_, entry)| entry.needs_translation(self.include_fuzzy))
+    }
+
+    // 复数条目的每种形式分别翻译, 找不到示例 n 的形式留空并标记为 fuzzy, 返回的 bool 表示是否有留空的形式
+    fn strings(&self, number: usize, entry: &Entry, translations: &HashMap<String, String>) -> Option<(Vec<String>, bool)> {
+        if entry.plural.is_none() {
+            return Some((vec![translations.get(&number.to_string())?.clone()], false));
+        }
+        if self.samples.iter().all(Option::is_none) {
+            return None;
+        }
+        let mut strings = Vec::new();
+        for (index, sample) in self.samples.iter().enumerate() {
+            match sample {
+                Some(_) => strings.push(translations.get(&format!("{}.{}", number, index))?.clone()),
+                None => strings.push(String::new()),
+            }
+        }
+        Some((strings, self.samples.contains(&None)))
+    }
+
+    // 只有一种复数形式时使用 msgid_plural, 否则第一种形式使用 msgid, 其余使用 msgid_plural
+    fn plural_source<'e>(&self, entry: &'e Entry, index: usize) -> &'e str {
+        match (&entry.plural, index) {
+            (Some(plural), index) if index > 0 || self.plurals == 1 => plural,
+            _ => entry.id.as_deref().unwrap_or_default(),
+        }
+    }
+}
+
+impl Document for Po {
+    fn units(&self) -> Vec<Unit> {
+        let mut units = Vec::new();
+        for (number, entry) in self.translatable() {
+            if entry.plural.is_none() {
+                let (text, tags) = mask(entry.id.as_deref().unwrap_or_default(), &FORMAT);
+                units.push(Unit { id: number.to_string(), text, tags });
+                continue;
+            }
+            for (index, _) in self.samples.iter().enumerate().filter(|(_, sample)| sample.is_some()) {
+                let (text, tags) = mask(self.plural_source(entry, index), &FORMAT);
+                units.push(Unit { id: format!("{}.{}", number, index), text, tags });
+            }
+        }
+        units
+    }
+
+    fn render(&self, translations: &HashMap<String, String>) -> String {
+        let mut blocks = Vec::new();
+        for (i, entry) in self.entries.iter().enumerate() {
+            let strings = entry.needs_translation(self.include_fuzzy)
+                .then(|| self.strings(i + 1, entry, translations))
+                .flatten();
+            let lines = match strings {
+                Some((strings, incomplete)) => entry.render(&strings, self.mark_fuzzy || incomplete),
+                None => entry.lines.iter().map(|(_, line)| line.clone()).collect(),
+            };
+            blocks.push(lines.join("\n"));
+        }
+        format!("{}\n", blocks.join("\n\n"))
+    }
+
+    // msgctxt 和提取的注释作为上文发送, 复数形式还附上 plural 规则和这一形式的示例 n
+    fn note(&self, id: &str) -> Option<String> {
+        let (number, plural) = match id.split_once('.') {
+            Some((number, index)) => (number, index.parse::<usize>().ok()),
+            None => (id, None),
+        };
+        let entry = self.entries.get(number.parse::<usize>().ok()?.checked_sub(1)?)?;
+        let mut notes = Vec::new();
+        if let Some(context) = &entry.context {
+            notes.push(format!("msgctxt: {}", context));
+        }
+        notes.extend(entry.comments.iter().filter(|comment| !comment.is_empty()).cloned());
+        if let Some(index) = plural {
+            let sample = self.samples.get(index).copied().flatten()?;
+            notes.push(format!("复数形式 msgstr[{}], plural={}, 用于 n={} 等", index, self.rule, sample));
+        }
+        (!notes.is_empty()).then(|| notes.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = r#"msgid ""
+msgstr ""
+"Content-Type: text/plain; charset=UTF-8\n"
+"Plural-Forms: nplurals=2; plural=(n != 1);\n"
+
+#. 按钮文字
+#: src/main.c:12
+msgctxt "menu"
+msgid "Open %s"
+msgstr ""
+
+#, fuzzy
+#| msgid "Old"
+msgid "Close"
+msgstr "关"
+
+msgid "One file"
+msgid_plural "%d files"
+msgstr[0] ""
+msgstr[1] ""
+
+msgid ""
+"Line one\n"
+"Line two"
+msgstr ""
+"#;
+
+    fn options(include_fuzzy: bool, mark_fuzzy: bool) -> DocumentOptions {
+        DocumentOptions { include_fuzzy, mark_fuzzy, ..DocumentOptions::default() }
+    }
+
+    #[test]
+    fn fills_msgstr_and_reparses_as_translated() {
+        let po = parse(SOURCE, "zh_CN", &options(false, false)).unwrap();
+        assert_eq!(po.render(&HashMap::new()), SOURCE);
+
+        let translations = po.restore_tags(&HashMap::from([
+            ("2".to_string(), "打开 [[0]]".to_string()),
+            ("4.0".to_string(), "一个文件".to_string()),
+            ("4.1".to_string(), "[[0]] 个文件".to_string()),
+            ("5".to_string(), "第一行\n第二行".to_string()),
+        ]));
+        let output = po.render(&translations);
+        assert!(output.contains("msgctxt \"menu\"\nmsgid \"Open %s\"\nmsgstr \"打开 %s\""));
+        assert!(output.contains("msgstr[0] \"一个文件\"\nmsgstr[1] \"%d 个文件\""));
+        assert!(output.contains("msgstr \"\"\n\"第一行\\n\"\n\"第二行\""));
+        assert!(output.contains("#, fuzzy\n#| msgid \"Old\"\nmsgid \"Close\"\nmsgstr \"关\""));
+
+        let translated = parse(&output, "zh_CN", &options(false, false)).unwrap();
+        assert!(translated.units().is_empty());
+        assert_eq!(translated.render(&HashMap::new()), output);
+    }
+
+    #[test]
+    fn keeps_obsolete_entries_untranslated() {
+        let source = "msgid \"Keep\"\nmsgstr \"\"\n\n#~ msgid \"Removed\"\n#~ msgstr \"已删除\"\n";
+        let po = parse(source, "zh_CN", &options(true, false)).unwrap();
+        let units = po.units();
+        assert_eq!(units.len(), 1);
+        assert_eq!(units[0].text, "Keep");
+
+        let translations = HashMap::from([(units[0].id.clone(), "保留".to_string())]);
+        assert_eq!(po.render(&translations), "msgid \"Keep\"\nmsgstr \"保留\"\n\n#~ msgid \"Removed\"\n#~ msgstr \"已删除\"\n");
+    }
+
+    #[test]
+    fn masks_format_specifiers_and_adds_notes() {
+        let po = parse(SOURCE, "zh_CN", &options(false, false)).unwrap();
+        let units = po.units();
+        let ids: Vec<&str> = units.iter().map(|unit| unit.id.as_str()).collect();
+        assert_eq!(ids, vec!["2", "4.0", "4.1", "5"]);
+        assert_eq!(units[0].text, "Open [[0]]");
+        assert_eq!(units[2].tags, vec!["%d"]);
+        assert_eq!(po.note("2").as_deref(), Some("msgctxt: menu; 按钮文字"));
+        assert_eq!(units[1].text, "One file");
+        assert_eq!(po.note("4.1").as_deref(), Some("复数形式 msgstr[1], plural=(n != 1), 用于 n=0 等"));
+    }
+
+    #[test]
+    fn retranslates_and_marks_fuzzy_entries() {
+        let po = parse(SOURCE, "zh_CN", &options(true, true)).unwrap();
+        assert!(po.units().iter().any(|unit| unit.id == "3"));
+        let translations = HashMap::from([("3".to_string(), "关闭".to_string())]);
+        assert!(po.render(&translations).contains("#, fuzzy\nmsgid \"Close\"\nmsgstr \"关闭\""));
+
+        let po = parse(SOURCE, "zh_CN", &options(true, false)).unwrap();
+        assert!(po.render(&translations).contains("\n\nmsgid \"Close\"\nmsgstr \"关闭\""));
+    }
+
+    #[test]
+    fn fills_template_header_for_target_language() {
+        let template = "msgid \"\"\nmsgstr \"\"\n\"Language: \\n\"\n\"MIME-Version: 1.0\\n\"\n\nmsgid \"One\"\nmsgid_plural \"Many\"\nmsgstr[0] \"\"\nmsgstr[1] \"\"\n";
+        let po = parse(template, "ru", &options(false, false)).unwrap();
+        let units = po.units();
+        let ids: Vec<&str> = units.iter().map(|unit| unit.id.as_str()).collect();
+        assert_eq!(ids, vec!["2.0", "2.1", "2.2"]);
+        assert_eq!(units[0].text, "One");
+        assert_eq!(units[2].text, "Many");
+        assert!(po.note("2.1").unwrap().ends_with("用于 n=2 等"));
+        assert!(po.note("2.2").unwrap().ends_with("用于 n=0 等"));
+
+        let translations = HashMap::from([
+            ("2.0".to_string(), "Один".to_string()),
+            ("2.1".to_string(), "Несколько".to_string()),
+            ("2.2".to_string(), "Много".to_string()),
+        ]);
+        let output = po.render(&translations);
+        assert!(output.starts_with("msgid \"\"\nmsgstr \"\"\n\"Language: ru\\n\"\n\"MIME-Version: 1.0\\n\"\n\"Plural-Forms: nplurals=3;"));
+        assert!(output.contains("msgstr[0] \"Один\"\nmsgstr[1] \"Несколько\"\nmsgstr[2] \"Много\""));
+
+        let po = parse(template, "zh_CN", &options(false, false)).unwrap();
+        let units = po.units();
+        assert_eq!(units.len(), 1);
+        assert_eq!((units[0].id.as_str(), units[0].text.as_str()), ("2.0", "Many"));
+        let translations = HashMap::from([("2.0".to_string(), "许多".to_string())]);
+        assert!(po.render(&translations).contains("msgid_plural \"Many\"\nmsgstr[0] \"许多\"\n"));
+    }
+
+    #[test]
+    fn leaves_unreachable_plural_forms_empty_and_fuzzy() {
+        let source = "msgid \"\"\nmsgstr \"Plural-Forms: nplurals=3; plural=(n != 1);\\n\"\n\nmsgid \"One\"\nmsgid_plural \"Many\"\nmsgstr[0] \"\"\nmsgstr[1] \"\"\nmsgstr[2] \"\"\n";
+        let po = parse(source, "xx", &options(false, false)).unwrap();
+        let ids: Vec<String> = po.units().into_iter().map(|unit| unit.id).collect();
+        assert_eq!(ids, vec!["2.0", "2.1"]);
+
+        let translations = HashMap::from([("2.0".to_string(), "1".to_string()), ("2.1".to_string(), "2".to_string())]);
+        assert!(po.render(&translations).ends_with("#, fuzzy\nmsgid \"One\"\nmsgid_plural \"Many\"\nmsgstr[0] \"1\"\nmsgstr[1] \"2\"\nmsgstr[2] \"\"\n"));
+    }
+
+    #[test]
+    fn evaluates_plural_rules() {
+        let (_, arabic) = plural_forms("ar");
+        let indices: Vec<Option<u64>> = [0, 1, 2, 5, 11, 100].iter().map(|&n| plural_index(arabic, n)).collect();
+        assert_eq!(indices, vec![Some(0), Some(1), Some(2), Some(3), Some(4), Some(5)]);
+        assert_eq!(plural_index("(n==1) ? 0 : !(n%2) ? 1 : 2", 4), Some(1));
+        assert_eq!(plural_index("n % 0", 3), None);
+        assert_eq!(plural_index("(n > 1", 3), None);
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        assert!(parse("msgid \"a\"\nmsgstr \"b\"\nfoo\n", "zh", &options(false, false)).is_err());
+        assert!(parse("msgid \"unterminated\nmsgstr \"\"\n", "zh", &options(false, false)).is_err());
+        assert!(parse("# 只有注释\n", "zh", &options(false, false)).is_err());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::chunking::line_range;
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+const TTY_REFRESH: Duration = Duration::from_millis(200);
+const PLAIN_LOG_INTERVAL: Duration = Duration::from_secs(10);
+const BAR_WIDTH: usize = 30;
+const NAME_WIDTH: usize = 24;
+
+#[derive(Clone)]
+pub enum WorkerStatus {
+    Waiting,
+    Reading,
+    WaitingContext {
+        job: String,
+        index: usize,
+    },
+    Translating {
+        job: String,
+        index: usize,
+        start_line: usize,
+        end_line: usize,
+        since: Instant,
+    },
+    Finished,
+}
+
+// 终端中在底部刷新进度, 输出不是终端时定期打印一行进度
+pub struct Progress {
+    tty: bool,
+    inner: Mutex<ProgressState>,
+}
+
+struct ProgressState {
+    total_chunks: usize,
+    committed_chunks: usize,
+    failed_chunks: usize,
+    run_chunks: usize,
+    run_lines: usize,
+    run_tokens: u64,
+    started: Instant,
+    workers: Vec<WorkerStatus>,
+    drawn_lines: usize,
+}
+
+impl Progress {
+    // total_chunks 包括之前已经写入的块, committed_chunks 是其中已写入的块数
+    pub fn new(task_num: usize, total_chunks: usize, committed_chunks: usize, plain: bool) -> Arc<Self> {
+        Arc::new(Progress {
+            tty: !plain && std::io::stdout().is_terminal(),
+            inner: Mutex::new(ProgressState {
+                total_chunks,
+                committed_chunks,
+                failed_chunks: 0,
+                run_chunks: 0,
+                run_lines: 0,
+                run_tokens: 0,
+                started: Instant::now(),
+                workers: vec![WorkerStatus::Waiting; task_num],
+                drawn_lines: 0,
+            }),
+        })
+    }
+
+    pub fn set_worker(&self, worker: usize, status: WorkerStatus) {
+        self.inner.lock().unwrap().workers[worker] = status;
+    }
+
+    pub fn chunk_committed(&self, lines: usize, tokens: u64, failed: bool) {
+        let mut state = self.inner.lock().unwrap();
+        state.committed_chunks += 1;
+        state.run_chunks += 1;
+        state.run_lines += lines;
+        state.run_tokens += tokens;
+        if failed {
+            state.failed_chunks += 1;
+        }
+        state.total_chunks = state.total_chunks.max(state.committed_chunks);
+    }
+
+    // 在进度上方打印一行消息
+    pub fn log(&self, message: &str) {
+        let mut state = self.inner.lock().unwrap();
+        if !self.tty {
+            println!("{}", message);
+            return;
+        }
+
+        let mut stdout = std::io::stdout().lock();
+        clear(&mut stdout, &mut state);
+        let _ = writeln!(stdout, "{}", message);
+        draw(&mut stdout, &mut state);
+    }
+
+    pub fn spawn_renderer(self: &Arc<Self>) -> JoinHandle<()> {
+        let progress = Arc::clone(self);
+        tokio::spawn(async move {
+            let period = if progress.tty { TTY_REFRESH } else { PLAIN_LOG_INTERVAL };
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                progress.render();
+            }
+        })
+    }
+
+    fn render(&self) {
+        let mut state = self.inner.lock().unwrap();
+        if self.tty {
+            let mut stdout = std::io::stdout().lock();
+            clear(&mut stdout, &mut state);
+            draw(&mut stdout, &mut state);
+        } else {
+            println!("进度: {}", summary_line(&state));
+        }
+    }
+
+    // 结束时保留最后一次的进度, 之后的输出接在下面
+    pub fn finish(&self) {
+        let mut state = self.inner.lock().unwrap();
+        if self.tty {
+            let mut stdout = std::io::stdout().lock();
+            clear(&mut stdout, &mut state);
+            let _ = writeln!(stdout, "{}", summary_line(&state));
+            let _ = stdout.flush();
+        } else {
+            println!("进度: {}", summary_line(&state));
+        }
+    }
+}
+
+fn clear(stdout: &mut impl Write, state: &mut ProgressState) {
+    if state.drawn_lines > 0 {
+        let _ = write!(stdout, "\x1b[{}A\r\x1b[J", state.drawn_lines);
+        state.drawn_lines = 0;
+    }
+}
+
+fn draw(stdout: &mut impl Write, state: &mut ProgressState) {
+    let mut lines = vec![format!("{} {}", progress_bar(state), summary_line(state))];
+    for (i, worker) in state.workers.iter().enumerate() {
+        lines.push(format!("  工作流{}: {}", i, worker_line(worker)));
+    }
+
+    for line in &lines {
+        let _ = writeln!(stdout, "{}", line);
+    }
+    let _ = stdout.flush();
+    state.drawn_lines = lines.len();
+}
+
+fn progress_bar(state: &ProgressState) -> String {
+    let filled = BAR_WIDTH * state.committed_chunks / state.total_chunks.max(1);
+    format!("[{}{}]", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled))
+}
+
+fn summary_line(state: &ProgressState) -> String {
+    let minutes = state.started.elapsed().as_secs_f64() / 60.0;
+    let per_minute = |value: f64| if minutes > 0.0 { value / minutes } else { 0.0 };
+    let remaining = state.total_chunks.saturating_sub(state.committed_chunks);
+
+    let eta = if remaining == 0 {
+        "00:00".to_string()
+    } else if state.run_chunks == 0 {
+        "--:--".to_string()
+    } else {
+        let secs = state.started.elapsed().as_secs_f64() / state.run_chunks as f64 * remaining as f64;
+        format_duration(secs as u64)
+    };
+
+    format!(
+        "{}/{} 块 ({}%), 失败 {} | {:.0} 行/分 | {:.0} tokens/分 | 预计剩余 {}",
+        state.committed_chunks,
+        state.total_chunks,
+        state.committed_chunks * 100 / state.total_chunks.max(1),
+        state.failed_chunks,
+        per_minute(state.run_lines as f64),
+        per_minute(state.run_tokens as f64),
+        eta
+    )
+}
+
+fn worker_line(worker: &WorkerStatus) -> String {
+    match worker {
+        WorkerStatus::Waiting => "等待写入窗口".to_string(),
+        WorkerStatus::Reading => "读取下一块".to_string(),
+        WorkerStatus::WaitingContext { job, index } => {
+            format!("{} chunk {} 等待上一块写入", truncate(job, NAME_WIDTH), index)
+        }
+        WorkerStatus::Translating { job, index, start_line, end_line, since } => format!(
+            "{} chunk {} (行 {}) 翻译中 {:.1}s",
+            truncate(job, NAME_WIDTH),
+            index,
+            line_range(*start_line, *end_line),
+            since.elapsed().as_secs_f64()
+        ),
+        WorkerStatus::Finished => "已结束".to_string(),
+    }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let head: String = text.chars().take(width - 1).collect();
+    format!("{}…", head)
+}
+
+fn format_duration(secs: u64) -> String {
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
@@ -0,0 +1,458 @@
+use crate::align::{AlignOptions, DEFAULT_ALIGN_RETRIES};
+use crate::chapters::ChapterSettings;
+use crate::chunking::{ChunkMode, ChunkingOptions};
+use crate::config::{load_api_config, ConfigData};
+use crate::document::{DocumentOptions, DocumentState, Format};
+use crate::dry_run::{dry_run, DryRunFlags, EstimateSettings};
+use crate::encoding::EncodingOptions;
+use crate::file_operations::{get_filename, read_file_content, remove_extension, write_json_overwrite, CONFIG_DIR, REPORT_DIR};
+use crate::line_ending::LineEndings;
+use crate::pipeline::{
+    default_reorder_window, run_jobs, state_file_name, Job, JobReport, PipelineOptions, RunSummary, DEFAULT_SHUTDOWN_GRACE_SECS,
+};
+use crate::retry::{retry_chunks, ChunkSelection, RetryOptions};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// 项目清单, 用于把一套多卷小说作为一个整体翻译
+#[derive(Deserialize)]
+pub struct ProjectManifest {
+    pub name: String,
+    pub files: Vec<String>,
+    pub languages: Vec<LanguagePair>,
+    #[serde(default)]
+    pub glossary: Option<String>,
+    #[serde(default)]
+    pub glossaries: HashMap<String, String>,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub chunking: ChunkingPolicy,
+    #[serde(default)]
+    pub output: OutputLayout,
+    #[serde(default = "default_task_num")]
+    pub task_num: usize,
+    #[serde(default = "default_output_key")]
+    pub output_key: String,
+    #[serde(default)]
+    pub reorder_window: Option<usize>,
+    #[serde(default)]
+    pub shutdown_grace_secs: Option<u64>,
+    #[serde(default)]
+    pub estimate: EstimateSettings,
+    #[serde(default)]
+    pub context_lines: usize,
+    #[serde(default)]
+    pub align_lines: bool,
+    #[serde(default)]
+    pub align_retries: Option<usize>,
+    #[serde(default)]
+    pub preserve_layout: bool,
+    #[serde(default)]
+    pub source_encoding: Option<String>,
+    #[serde(default)]
+    pub chapters: ChapterSettings,
+    #[serde(default)]
+    pub documents: DocumentOptions,
+}
+
+impl ProjectManifest {
+    // preserve_layout 依赖逐行对齐, 开启时也会开启对齐模式
+    fn alignment(&self) -> Option<AlignOptions> {
+        (self.align_lines || self.preserve_layout).then(|| AlignOptions {
+            retries: self.align_retries.unwrap_or(DEFAULT_ALIGN_RETRIES),
+            preserve_layout: self.preserve_layout,
+            line_endings: self.output.line_endings,
+        })
+    }
+
+    fn chunking(&self, prompt_tokens: usize) -> Result<ChunkingOptions, String> {
+        let mut chunking = self.chunking.options(prompt_tokens)?;
+        chunking.chapters = self.chapters.matcher()?.map(Arc::new);
+        Ok(chunking)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LanguagePair {
+    pub source: String,
+    pub target: String,
+}
+
+// strategy 为 lines 时每块 num_lines 行, 否则按 max_tokens 装入行、段落或句子
+#[derive(Deserialize)]
+pub struct ChunkingPolicy {
+    #[serde(default)]
+    pub strategy: ChunkMode,
+    #[serde(default = "default_num_lines")]
+    pub num_lines: usize,
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub tokenizer: Option<String>,
+}
+
+impl Default for ChunkingPolicy {
+    fn default() -> Self {
+        ChunkingPolicy {
+            strategy: ChunkMode::default(),
+            num_lines: default_num_lines(),
+            max_tokens: None,
+            tokenizer: None,
+        }
+    }
+}
+
+impl ChunkingPolicy {
+    fn options(&self, prompt_tokens: usize) -> Result<ChunkingOptions, String> {
+        match self.strategy {
+            ChunkMode::Lines => Ok(ChunkingOptions::lines(self.num_lines, prompt_tokens)),
+            mode => {
+                let max_tokens = self.max_tokens.ok_or("chunking.strategy 不是 lines 时需要设置 max_tokens")?;
+                ChunkingOptions::packed(mode, max_tokens, prompt_tokens, self.tokenizer.as_deref())
+            }
+        }
+    }
+}
+
+// file_name 支持 {name}、{path}、{source}、{target}、{ext} 占位符, 可以包含子目录
+// {path} 是源文件相对于清单所在目录的路径 (不含扩展名), 不同目录下有同名文件时使用
+// encoding 是输出文件的编码, 默认为utf-8, bom 为 true 时在文件开头写入 BOM
+// line_endings 默认与源文件相同, 也可以统一为 lf 或 crlf
+#[derive(Deserialize)]
+pub struct OutputLayout {
+    #[serde(default = "default_output_dir")]
+    pub dir: String,
+    #[serde(default = "default_output_file_name")]
+    pub file_name: String,
+    #[serde(default)]
+    pub encoding: Option<String>,
+    #[serde(default)]
+    pub bom: bool,
+    #[serde(default)]
+    pub line_endings: LineEndings,
+}
+
+impl Default for OutputLayout {
+    fn default() -> Self {
+        OutputLayout {
+            dir: default_output_dir(),
+            file_name: default_output_file_name(),
+            encoding: None,
+            bom: false,
+            line_endings: LineEndings::default(),
+        }
+    }
+}
+
+fn default_task_num() -> usize {
+    4
+}
+
+fn default_output_key() -> String {
+    "output".to_string()
+}
+
+fn default_num_lines() -> usize {
+    20
+}
+
+fn default_output_dir() -> String {
+    "translation".to_string()
+}
+
+fn default_output_file_name() -> String {
+    "{name}_{source}2{target}.{ext}".to_string()
+}
+
+#[derive(Serialize)]
+struct ProjectReport<'a> {
+    project: &'a str,
+    interrupted: bool,
+    chunks_done: usize,
+    chunks_failed: usize,
+    jobs: &'a [JobReport],
+}
+
+pub fn load_manifest(manifest_path: &str) -> Result<ProjectManifest, String> {
+    let yaml_str = fs::read_to_string(manifest_path)
+        .map_err(|_| format!("无法读取项目清单: {}", manifest_path))?;
+
+    serde_yaml::from_str(&yaml_str)
+        .map_err(|e| format!("解析项目清单失败: {}", e))
+}
+
+pub async fn translate_project(manifest_path: &str, reconcile: bool, grace: Option<u64>) -> Result<i32, String> {
+    let manifest = load_manifest(manifest_path)?;
+    let base_dir = manifest_dir(manifest_path);
+
+    let api_config = load_api_config(&format!("{}/user.yaml", CONFIG_DIR), manifest.profile.as_deref())?;
+    let jobs = build_jobs(&manifest, &base_dir)?;
+    if jobs.is_empty() {
+        return Err("项目清单中没有匹配到任何文件".to_string());
+    }
+
+    println!("项目 {} 共 {} 个翻译任务", manifest.name, jobs.len());
+
+    let options = PipelineOptions {
+        task_num: manifest.task_num,
+        chunking: manifest.chunking(manifest.estimate.prompt_tokens)?,
+        output_key: manifest.output_key.clone(),
+        reconcile,
+        reorder_window: manifest.reorder_window.unwrap_or_else(|| default_reorder_window(manifest.task_num)),
+        shutdown_grace_secs: grace.or(manifest.shutdown_grace_secs).unwrap_or(DEFAULT_SHUTDOWN_GRACE_SECS),
+        context_lines: manifest.context_lines,
+        alignment: manifest.alignment(),
+        line_endings: manifest.output.line_endings,
+        chapter_files: manifest.chapters.split_files,
+        documents: manifest.documents.clone(),
+    };
+    let summary = run_jobs(jobs, Arc::new(api_config), options).await?;
+
+    write_project_report(&manifest.name, &summary).await?;
+    Ok(summary.exit_code())
+}
+
+pub async fn dry_run_project(manifest_path: &str, flags: DryRunFlags) -> Result<(), String> {
+    let manifest = load_manifest(manifest_path)?;
+    let jobs = build_jobs(&manifest, &manifest_dir(manifest_path))?;
+    if jobs.is_empty() {
+        return Err("项目清单中没有匹配到任何文件".to_string());
+    }
+
+    let chunking = manifest.chunking(flags.prompt_tokens(&manifest.estimate))?;
+    let options = flags.into_options(chunking, &manifest.estimate);
+    dry_run(&jobs, &options).await
+}
+
+pub async fn retry_project(manifest_path: &str, chunks: Option<&str>) -> Result<(), String> {
+    let selection = ChunkSelection::parse(chunks)?;
+    let manifest = load_manifest(manifest_path)?;
+    let api_config = load_api_config(&format!("{}/user.yaml", CONFIG_DIR), manifest.profile.as_deref())?;
+    let jobs = build_jobs(&manifest, &manifest_dir(manifest_path))?;
+
+    let options = RetryOptions {
+        task_num: manifest.task_num,
+        output_key: manifest.output_key.clone(),
+        alignment: manifest.alignment(),
+        line_endings: manifest.output.line_endings,
+        chapter_files: manifest.chapters.split_files,
+    };
+    retry_chunks(jobs, Arc::new(api_config), options, &selection).await
+}
+
+pub fn show_project_status(manifest_path: &str) -> Result<(), String> {
+    let manifest = load_manifest(manifest_path)?;
+    let jobs = build_jobs(&manifest, &manifest_dir(manifest_path))?;
+
+    println!("项目 {}", manifest.name);
+    for job in &jobs {
+        match job.format {
+            Format::Text => job.load_state()?.print_summary(),
+            _ => DocumentState::load(job)?.print_summary(),
+        }
+    }
+    Ok(())
+}
+
+fn manifest_dir(manifest_path: &str) -> PathBuf {
+    Path::new(manifest_path)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default()
+}
+
+fn resolve_path(base_dir: &Path, path: &str) -> String {
+    base_dir.join(path).to_string_lossy().to_string()
+}
+
+fn expand_files(manifest: &ProjectManifest, base_dir: &Path) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+
+    for pattern in &manifest.files {
+        let pattern = resolve_path(base_dir, pattern);
+        let paths = glob::glob(&pattern).map_err(|e| format!("无效的文件模式 {}: {}", pattern, e))?;
+
+        let mut matched = false;
+        for path in paths.flatten() {
+            let path = path.to_string_lossy().to_string();
+            if !files.contains(&path) {
+                files.push(path);
+            }
+            matched = true;
+        }
+
+        if !matched {
+            println!("未匹配到文件: {}", pattern);
+        }
+    }
+
+    Ok(files)
+}
+
+// 共享术语表在前, 单个文件的术语表在后
+fn load_glossary(manifest: &ProjectManifest, base_dir: &Path, file_name: &str) -> Result<String, String> {
+    let mut glossary = Vec::new();
+
+    let paths = manifest.glossary.iter().chain(manifest.glossaries.get(file_name));
+    for path in paths {
+        let path = resolve_path(base_dir, path);
+        let content = read_file_content(&path).map_err(|e| format!("无法读取术语表 {}: {}", path, e))?;
+        if !content.trim().is_empty() {
+            glossary.push(content.trim_end().to_string());
+        }
+    }
+
+    Ok(glossary.join("\n"))
+}
+
+fn build_jobs(manifest: &ProjectManifest, base_dir: &Path) -> Result<Vec<Job>, String> {
+    let output_dir = resolve_path(base_dir, &manifest.output.dir);
+    let encodings = EncodingOptions::parse(
+        manifest.source_encoding.as_deref(),
+        manifest.output.encoding.as_deref(),
+        manifest.output.bom,
+    )?;
+    let mut jobs = Vec::new();
+    let mut outputs = HashMap::new();
+    let mut states = HashSet::new();
+
+    for source_path in expand_files(manifest, base_dir)? {
+        let file_name = get_filename(&source_path).map_err(|e| e.to_string())?;
+        let name = remove_extension(&file_name);
+        let relative = Path::new(&source_path).strip_prefix(base_dir).unwrap_or(Path::new(&source_path));
+        let relative_name = relative.with_extension("").to_string_lossy().to_string();
+        let state_name = state_key(&name, relative);
+        let term = Arc::new(load_glossary(manifest, base_dir, &file_name)?);
+        let source_encoding = encodings.source_encoding(&source_path);
+        let format = Format::from_path(&source_path);
+
+        for pair in &manifest.languages {
+            let config_data = ConfigData {
+                target_lang: pair.target.clone(),
+                source_lang: pair.source.clone(),
+            };
+            let state_file_name = state_file_name(&state_name, &config_data, format);
+            if !states.insert(state_file_name.clone()) {
+                return Err(format!("{} 的任务状态文件 {} 与其他文件重复", source_path, state_file_name));
+            }
+
+            let output_file_name = manifest.output.file_name
+                .replace("{name}", &name)
+                .replace("{path}", &relative_name)
+                .replace("{source}", &pair.source)
+                .replace("{target}", &pair.target)
+                .replace("{ext}", format.extension());
+            let output_path = format!("{}/{}", output_dir, output_file_name);
+            if let Some(other) = outputs.insert(output_path.clone(), source_path.clone()) {
+                return Err(format!(
+                    "{} 和 {} 的输出文件相同: {}, 请在 output.file_name 中使用 {{path}} 区分",
+                    other, source_path, output_path
+                ));
+            }
+
+            jobs.push(Job {
+                name: relative_name.clone(),
+                source_path: source_path.clone(),
+                config_data,
+                legacy_config_path: Some(Path::new(CONFIG_DIR).join(&state_file_name)),
+                state_file_name,
+                term: Arc::clone(&term),
+                term_file_name: None,
+                output_dir: output_dir.clone(),
+                output_file_name,
+                source_encoding,
+                output_encoding: encodings.output,
+                format,
+            });
+        }
+    }
+
+    Ok(jobs)
+}
+
+// 清单目录下的文件沿用文件名作为进度文件名, 子目录中的文件加上目录的哈希, 避免不同目录的同名文件共用进度
+fn state_key(name: &str, relative: &Path) -> String {
+    match relative.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => {
+            let hash = format!("{:x}", Sha256::digest(dir.to_string_lossy().as_bytes()));
+            format!("{}_{}", name, &hash[..8])
+        }
+        None => name.to_string(),
+    }
+}
+
+async fn write_project_report(project: &str, summary: &RunSummary) -> Result<(), String> {
+    let reports = &summary.reports;
+    let report = ProjectReport {
+        project,
+        interrupted: summary.interrupted,
+        chunks_done: reports.iter().map(|r| r.chunks_done).sum(),
+        chunks_failed: reports.iter().map(|r| r.chunks_failed).sum(),
+        jobs: reports,
+    };
+
+    if summary.interrupted {
+        println!("\n项目 {} 翻译已中断", project);
+    } else {
+        println!("\n项目 {} 翻译完成", project);
+    }
+    for r in reports {
+        println!(
+            "  {} ({}→{}): 成功 {} 块, 失败 {} 块, 耗时 {:.1}s -> {}",
+            r.name, r.source_lang, r.target_lang, r.chunks_done, r.chunks_failed, r.elapsed_secs, r.output
+        );
+    }
+    println!(
+        "合计: 成功 {} 块, 失败 {} 块, tokens {}",
+        report.chunks_done,
+        report.chunks_failed,
+        reports.iter().map(|r| r.total_tokens).sum::<u64>()
+    );
+
+    write_json_overwrite(REPORT_DIR, &format!("{}_report.json", project), &report)
+        .await
+        .map_err(|e| format!("无法写入项目报告: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(name: &str, file_name: &str) -> (PathBuf, ProjectManifest) {
+        let dir = std::env::temp_dir().join(format!("dify_translation_project_{}_{}", name, std::process::id()));
+        for volume in ["vol1", "vol2"] {
+            fs::create_dir_all(dir.join(volume)).unwrap();
+            fs::write(dir.join(volume).join("ch01.txt"), "一\n").unwrap();
+        }
+        let yaml = format!(
+            "name: series\nfiles: [\"*/ch01.txt\"]\nlanguages: [{{source: ja, target: zh}}]\noutput:\n  file_name: \"{}\"\n",
+            file_name
+        );
+        (dir, serde_yaml::from_str(&yaml).unwrap())
+    }
+
+    #[test]
+    fn keys_same_named_files_by_directory() {
+        let (dir, manifest) = setup("path", "{path}_{source}2{target}.{ext}");
+        let jobs = build_jobs(&manifest, &dir).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_ne!(jobs[0].state_file_name, jobs[1].state_file_name);
+        assert!(jobs[0].state_file_name.starts_with("ch01_"));
+        assert_eq!(jobs[0].output_file_name, "vol1/ch01_ja2zh.txt");
+        assert_eq!(jobs[1].output_file_name, "vol2/ch01_ja2zh.txt");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_duplicate_output_paths() {
+        let (dir, manifest) = setup("duplicate", "{name}_{source}2{target}.{ext}");
+        let error = build_jobs(&manifest, &dir).err().unwrap();
+        assert!(error.contains("输出文件相同"), "{}", error);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+use crate::align::{AlignOptions, Aligner, Repair};
+use crate::api::WorkflowResult;
+use crate::chapters::write_chapter_files;
+use crate::config::APIConfig;
+use crate::document::Format;
+use crate::file_operations::{read_source_text, trim_source_text, write_bytes_overwrite};
+use crate::line_ending::LineEndings;
+use crate::pipeline::{extract_translation, format_translation, process_task, Job};
+use crate::state::{ChunkStatus, Usage};
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+// 默认重试所有失败的块, 也可以指定块编号重新翻译, 例如 "3,5-7"
+pub enum ChunkSelection {
+    Failed,
+    Indices(Vec<usize>),
+}
+
+impl ChunkSelection {
+    pub fn parse(spec: Option<&str>) -> Result<Self, String> {
+        let Some(spec) = spec else {
+            return Ok(ChunkSelection::Failed);
+        };
+
+        let mut indices = Vec::new();
+        for part in spec.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let invalid = || format!("无效的块编号: {}", part);
+            match part.split_once('-') {
+                Some((start, end)) => {
+                    let start: usize = start.trim().parse().map_err(|_| invalid())?;
+                    let end: usize = end.trim().parse().map_err(|_| invalid())?;
+                    indices.extend(start..=end);
+                }
+                None => indices.push(part.parse().map_err(|_| invalid())?),
+            }
+        }
+        Ok(ChunkSelection::Indices(indices))
+    }
+
+    fn contains(&self, index: usize, status: ChunkStatus) -> bool {
+        match self {
+            ChunkSelection::Failed => status == ChunkStatus::Failed,
+            ChunkSelection::Indices(indices) => indices.contains(&index),
+        }
+    }
+}
+
+pub struct RetryOptions {
+    pub task_num: usize,
+    pub output_key: String,
+    pub alignment: Option<AlignOptions>,
+    pub line_endings: LineEndings,
+    pub chapter_files: bool,
+}
+
+type RetryResult = Result<(String, WorkflowResult, Option<Repair>), String>;
+
+pub async fn retry_chunks(
+    jobs: Vec<Job>,
+    api_config: Arc<APIConfig>,
+    options: RetryOptions,
+    selection: &ChunkSelection
+) -> Result<(), String> {
+    let semaphore = Arc::new(Semaphore::new(options.task_num.max(1)));
+    let output_key = Arc::new(options.output_key);
+
+    for job in jobs {
+        // 结构化文件的进度按段记录, 再次翻译时只会发送失败和未翻译的段落
+        if job.format != Format::Text {
+            println!("{} 不是纯文本文件, 再次运行翻译即可重新翻译失败的段落", job.source_path);
+            continue;
+        }
+        let job = Arc::new(job);
+        let mut state = job.load_state()?;
+        state.discard_in_flight();
+
+        state.check_output_encoding(&job.output_encoding.label()).map_err(|e| format!("{}: {}", job.name, e))?;
+        // 旧版本的记录没有字节位置, 先从源文件补上
+        state.fill_byte_offsets(&job.source_path).map_err(|e| format!("无法读取源文件 {}: {}", job.source_path, e))?;
+
+        let output_path = job.output_path();
+        let output = fs::read(&output_path).unwrap_or_default();
+        if output.len() as u64 != state.output_len() {
+            return Err(format!(
+                "输出文件 {} 与记录不一致, 请先使用 --reconcile 继续翻译后再重试",
+                output_path
+            ));
+        }
+
+        let targets: Vec<_> = state.chunks.iter()
+            .filter(|chunk| selection.contains(chunk.index, chunk.status))
+            .map(|chunk| (chunk.index, chunk.start_byte, chunk.end_byte))
+            .collect();
+        let indices: Vec<usize> = targets.iter().map(|(index, _, _)| *index).collect();
+        if let Some(index) = state.first_changed_among(&job.source_path, &indices)? {
+            return Err(format!(
+                "源文件 {} 第 {} 块与记录不一致, 请先使用 --reconcile 继续翻译后再重试",
+                job.source_path, index
+            ));
+        }
+        if targets.is_empty() {
+            println!("{} ({}→{}) 没有需要重新翻译的块", job.name, job.config_data.source_lang, job.config_data.target_lang);
+            continue;
+        }
+
+        println!("{} 正在重新翻译 {} 块", job.name, targets.len());
+        let mut tasks = JoinSet::new();
+        for (index, start_byte, end_byte) in targets {
+            let job = Arc::clone(&job);
+            let api_config = Arc::clone(&api_config);
+            let semaphore = Arc::clone(&semaphore);
+            let output_key = Arc::clone(&output_key);
+            let alignment = options.alignment;
+            let line_endings = options.line_endings;
+
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire().await.unwrap();
+                let result = match read_source_text(&job.source_path, job.source_encoding, start_byte, end_byte).await {
+                    Ok(raw) => match alignment {
+                        Some(options) => retry_aligned(&job, &api_config, &output_key, options, &raw).await,
+                        None => {
+                            let result = process_task(&job.config_data, &api_config, &job.term, trim_source_text(&raw), None).await;
+                            let result = format_translation(result, &output_key, line_endings, &raw);
+                            extract_translation(result, &output_key).map(|(translation, result)| (translation, result, None))
+                        }
+                    },
+                    Err(e) => Err(format!("无法读取源文件: {}", e)),
+                };
+                (index, result)
+            });
+        }
+
+        let mut results: HashMap<usize, RetryResult> = HashMap::new();
+        while let Some(joined) = tasks.join_next().await {
+            let (index, result) = joined.map_err(|e| e.to_string())?;
+            results.insert(index, result);
+        }
+
+        // 按记录顺序重新拼接输出, 替换重试成功的块, 并更新之后各块的位置
+        let mut spliced = Vec::with_capacity(output.len());
+        let (mut done, mut failed) = (0, 0);
+        for chunk in state.chunks.iter_mut() {
+            let start = chunk.output_offset as usize;
+            let mut bytes = output[start..start + chunk.output_len as usize].to_vec();
+
+            match results.remove(&chunk.index) {
+                Some(Ok((translation, result, repair))) => {
+                    bytes = job.output_encoding.encode(&translation, spliced.is_empty()).0;
+                    chunk.status = ChunkStatus::Done;
+                    chunk.attempts += 1;
+                    chunk.error = None;
+                    chunk.repair = repair;
+                    chunk.usage = Some(Usage {
+                        total_tokens: result.total_tokens,
+                        elapsed_time: result.elapsed_time,
+                    });
+                    done += 1;
+                    println!("{} chunk {} 已重新翻译", job.name, chunk.index);
+                }
+                Some(Err(e)) => {
+                    chunk.attempts += 1;
+                    chunk.error = Some(e);
+                    failed += 1;
+                    println!("{} chunk {} 重新翻译失败", job.name, chunk.index);
+                }
+                None => {}
+            }
+
+            chunk.output_offset = spliced.len() as u64;
+            chunk.output_len = bytes.len() as u64;
+            spliced.extend_from_slice(&bytes);
+        }
+
+        write_bytes_overwrite(&job.output_dir, &job.output_file_name, &spliced)
+            .await
+            .map_err(|e| format!("无法写入输出文件 {}: {}", output_path, e))?;
+        // 偏移量已改变, 重新生成所有章节文件
+        if options.chapter_files {
+            write_chapter_files(&job, &mut state).await.map_err(|e| format!("{}: {}", job.name, e))?;
+        }
+        state.save(&job.state_file_name)
+            .await
+            .map_err(|e| format!("无法保存任务状态: {}", e))?;
+
+        println!("{} 重新翻译完成: 成功 {} 块, 失败 {} 块", job.name, done, failed);
+    }
+
+    Ok(())
+}
+
+// 对齐模式下按原文的每一行重新翻译, 开头和中间的空行也要对应
+async fn retry_aligned(job: &Job, api_config: &APIConfig, output_key: &str, options: AlignOptions, raw: &str) -> RetryResult {
+    let lines: Vec<String> = raw.split_inclusive('\n').map(str::to_string).collect();
+    let aligner = Aligner { job, api_config, output_key, options };
+    let (result, repair) = aligner.translate(&lines, None).await?;
+    let (translation, result) = extract_translation(Ok(result), output_key)?;
+    Ok((translation, result, repair))
+}
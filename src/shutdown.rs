@@ -0,0 +1,91 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Notify, Semaphore};
+use tokio::task::{AbortHandle, JoinHandle};
+
+// 收到 SIGINT/SIGTERM 后停止分发新的块, 第二次收到信号时立即退出
+pub struct Shutdown {
+    stopping: AtomicBool,
+    notify: Notify,
+}
+
+impl Shutdown {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Shutdown {
+            stopping: AtomicBool::new(false),
+            notify: Notify::new(),
+        })
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    pub fn trigger(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub async fn stopped(&self) {
+        let notified = self.notify.notified();
+        if self.is_stopping() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+pub fn listen(shutdown: Arc<Shutdown>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        wait_for_signal().await;
+        shutdown.trigger();
+
+        wait_for_signal().await;
+        println!("\n再次收到中断信号, 立即退出");
+        std::process::exit(130);
+    })
+}
+
+// 收到中断信号后关闭 gate 停止分发新的块或批次, 超过等待时间后终止仍在翻译的任务
+// log 用于输出提示, 显示进度条时需要经过进度条输出
+pub fn spawn_grace_timer(
+    shutdown: &Arc<Shutdown>,
+    gate: &Arc<Semaphore>,
+    aborts: Vec<AbortHandle>,
+    grace_secs: u64,
+    noun: &'static str,
+    log: impl Fn(&str) + Send + 'static
+) -> JoinHandle<()> {
+    let shutdown = Arc::clone(shutdown);
+    let gate = Arc::clone(gate);
+
+    tokio::spawn(async move {
+        shutdown.stopped().await;
+        log(&format!("收到中断信号, 停止分发新的{}, 等待翻译中的{}完成 (再次中断将立即退出)", noun, noun));
+        gate.close();
+        tokio::time::sleep(Duration::from_secs(grace_secs)).await;
+        if aborts.iter().any(|abort| !abort.is_finished()) {
+            log(&format!("等待超时, 放弃仍在翻译中的{}", noun));
+        }
+        for abort in aborts {
+            abort.abort();
+        }
+    })
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    tokio::signal::ctrl_c().await.unwrap();
+}
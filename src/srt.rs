@@ -0,0 +1,110 @@
+use crate::document::{Document, Unit};
+use std::collections::HashMap;
+
+// SubRip 字幕: 每条由序号、时间轴和若干行文本组成, 条目之间用空行分隔
+pub struct Srt {
+    cues: Vec<Cue>,
+}
+
+// 序号和时间轴原样保留, 有的文件省略了序号
+struct Cue {
+    index: Option<String>,
+    timing: String,
+    text: String,
+}
+
+pub fn parse(source: &str) -> Result<Srt, String> {
+    let mut cues: Vec<Cue> = Vec::new();
+    let lines: Vec<&str> = source.lines().collect();
+    let mut start = 0;
+
+    while start < lines.len() {
+        if lines[start].trim().is_empty() {
+            start += 1;
+            continue;
+        }
+        let end = lines[start..].iter().position(|line| line.trim().is_empty()).map_or(lines.len(), |len| start + len);
+        let block = &lines[start..end];
+
+        let (index, rest) = if block[0].contains("-->") {
+            (None, block)
+        } else {
+            (Some(block[0].trim().to_string()), &block[1..])
+        };
+        match rest.first() {
+            Some(timing) if timing.contains("-->") => cues.push(Cue {
+                index,
+                timing: timing.trim().to_string(),
+                text: rest[1..].join("\n"),
+            }),
+            // 文本中间的空行把一条字幕分成了两块, 归入前一条
+            _ => match cues.last_mut() {
+                Some(cue) if !block.iter().any(|line| line.contains("-->")) => {
+                    cue.text = format!("{}\n\n{}", cue.text, block.join("\n"));
+                }
+                _ => return Err(format!("第 {} 行的字幕缺少时间轴", start + 1)),
+            },
+        }
+        start = end;
+    }
+
+    if cues.is_empty() {
+        return Err("没有找到字幕".to_string());
+    }
+    Ok(Srt { cues })
+}
+
+impl Document for Srt {
+    // 按字幕在文件中的位置编号, 文件中的序号可能重复或不连续
+    fn units(&self) -> Vec<Unit> {
+        self.cues.iter()
+            .enumerate()
+            .map(|(i, cue)| Unit::new((i + 1).to_string(), cue.text.clone()))
+            .collect()
+    }
+
+    fn render(&self, translations: &HashMap<String, String>) -> String {
+        let mut output = String::new();
+        for (i, cue) in self.cues.iter().enumerate() {
+            if i > 0 {
+                output.push('\n');
+            }
+            if let Some(index) = &cue.index {
+                output.push_str(index);
+                output.push('\n');
+            }
+            output.push_str(&cue.timing);
+            output.push('\n');
+
+            // 译文中的空行会被当作字幕的结束, 需要去掉
+            let text = translations.get(&(i + 1).to_string()).unwrap_or(&cue.text);
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                output.push_str(line.trim_end());
+                output.push('\n');
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_blank_line_inside_cue_and_drops_it_from_translation() {
+        let srt = parse("1\n00:00:01,000 --> 00:00:02,000\n上\n\n下\n\n2\n00:00:03,000 --> 00:00:04,000\n后\n").unwrap();
+        let units = srt.units();
+        assert_eq!(units.len(), 2);
+        assert_eq!(units[0].text, "上\n\n下");
+
+        let translations = HashMap::from([("1".to_string(), "up\n\ndown".to_string())]);
+        assert_eq!(srt.render(&translations), "1\n00:00:01,000 --> 00:00:02,000\nup\ndown\n\n2\n00:00:03,000 --> 00:00:04,000\n后\n");
+    }
+
+    #[test]
+    fn rejects_cue_without_timing() {
+        assert!(parse("1\n字幕\n").is_err());
+        assert!(parse("\n\n").is_err());
+    }
+}
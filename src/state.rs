@@ -0,0 +1,435 @@
+use crate::align::Repair;
+use crate::chunking::line_range;
+use crate::file_operations::{write_json_overwrite, STATE_DIR};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStatus {
+    Pending,
+    InFlight,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Usage {
+    pub total_tokens: u64,
+    pub elapsed_time: f64,
+}
+
+// 一块源文本的翻译记录, 行号从0开始, end_line 不包含在内
+// hash 是源文件 start_byte..end_byte 之间原始字节的 SHA-256
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ChunkRecord {
+    pub index: usize,
+    pub start_line: usize,
+    pub end_line: usize,
+    #[serde(default)]
+    pub start_byte: u64,
+    #[serde(default)]
+    pub end_byte: u64,
+    pub hash: String,
+    pub status: ChunkStatus,
+    pub attempts: u32,
+    pub output_offset: u64,
+    pub output_len: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repair: Option<Repair>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub heading: bool,
+}
+
+// 单个翻译任务的进度, 与 config 中的用户设置分开保存在 state 目录
+// output_encoding 是已写入的输出文件使用的编码, 旧版本的记录都是 UTF-8
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobState {
+    pub source_path: String,
+    pub source_lang: String,
+    pub target_lang: String,
+    pub output_path: String,
+    #[serde(default = "default_output_encoding")]
+    pub output_encoding: String,
+    pub chunks: Vec<ChunkRecord>,
+    // 上次拆分出的章节文件名, 重新拆分时只删除这些文件
+    #[serde(default)]
+    pub chapter_files: Vec<String>,
+}
+
+fn default_output_encoding() -> String {
+    "UTF-8".to_string()
+}
+
+// 旧版本在 config/<name>.json 中记录的进度
+#[derive(Deserialize)]
+struct LegacyProgress {
+    history_lines: usize,
+}
+
+impl JobState {
+    pub fn new(source_path: &str, source_lang: &str, target_lang: &str, output_path: &str) -> Self {
+        JobState {
+            source_path: source_path.to_string(),
+            source_lang: source_lang.to_string(),
+            target_lang: target_lang.to_string(),
+            output_path: output_path.to_string(),
+            output_encoding: default_output_encoding(),
+            chunks: Vec::new(),
+            chapter_files: Vec::new(),
+        }
+    }
+
+    pub fn load(state_file_name: &str) -> io::Result<Option<Self>> {
+        let path = Path::new(STATE_DIR).join(state_file_name);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = fs::read_to_string(path)?;
+        let state = serde_json::from_str(&content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some(state))
+    }
+
+    pub async fn save(&self, state_file_name: &str) -> io::Result<()> {
+        write_json_overwrite(STATE_DIR, state_file_name, self).await
+    }
+
+    // 把旧版本 config 中的 history_lines 转换为一条已完成的记录
+    pub fn migrate_legacy(&mut self, config_file_path: &Path, output_len: u64) {
+        if !self.chunks.is_empty() {
+            return;
+        }
+
+        let legacy = fs::read_to_string(config_file_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<LegacyProgress>(&content).ok());
+
+        if let Some(legacy) = legacy.filter(|legacy| legacy.history_lines > 0) {
+            self.chunks.push(ChunkRecord {
+                index: 0,
+                start_line: 0,
+                end_line: legacy.history_lines,
+                start_byte: 0,
+                end_byte: 0,
+                hash: String::new(),
+                status: ChunkStatus::Done,
+                attempts: 1,
+                output_offset: 0,
+                output_len,
+                usage: None,
+                error: None,
+                repair: None,
+                heading: false,
+            });
+        }
+    }
+
+    // 已经按顺序写入输出文件的记录之后的第一行
+    pub fn next_line(&self) -> usize {
+        self.committed().last().map(|chunk| chunk.end_line).unwrap_or(0)
+    }
+
+    pub fn next_byte(&self) -> u64 {
+        self.committed().last().map(|chunk| chunk.end_byte).unwrap_or(0)
+    }
+
+    // 旧版本的记录只有行号, 根据行号补全字节位置和哈希
+    pub fn fill_byte_offsets(&mut self, source_path: &str) -> io::Result<()> {
+        if self.chunks.iter().all(|chunk| chunk.end_byte > 0 || chunk.end_line == 0) {
+            return Ok(());
+        }
+
+        let mut reader = BufReader::new(fs::File::open(source_path)?);
+        let mut offsets = vec![0u64];
+        let mut line = Vec::new();
+        let last_line = self.chunks.iter().map(|chunk| chunk.end_line).max().unwrap_or(0);
+        while offsets.len() <= last_line {
+            line.clear();
+            let bytes_read = reader.read_until(b'\n', &mut line)?;
+            if bytes_read == 0 {
+                break;
+            }
+            offsets.push(offsets.last().unwrap() + bytes_read as u64);
+        }
+
+        let mut file = fs::File::open(source_path)?;
+        for chunk in &mut self.chunks {
+            let (Some(&start), Some(&end)) = (offsets.get(chunk.start_line), offsets.get(chunk.end_line)) else {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "源文件行数少于记录"));
+            };
+            chunk.start_byte = start;
+            chunk.end_byte = end;
+            chunk.hash = hash_range(&mut file, start, end)?;
+        }
+        Ok(())
+    }
+
+    // 检查源文件和输出文件是否与记录一致
+    // reconcile 为 true 时回退到最后一个一致的位置, 否则返回错误
+    pub fn verify_resume(&mut self, source_path: &str, output_path: &str, reconcile: bool) -> Result<(), String> {
+        self.fill_byte_offsets(source_path)
+            .map_err(|e| format!("无法读取源文件 {}: {}", source_path, e))?;
+
+        if let Some(index) = self.first_changed_chunk(source_path)? {
+            let chunk = self.chunks.iter().find(|chunk| chunk.index == index).unwrap();
+            let message = format!(
+                "源文件 {} 在 chunk {} (行 {}) 处与上次记录不一致",
+                source_path,
+                index,
+                line_range(chunk.start_line, chunk.end_line)
+            );
+            if !reconcile {
+                return Err(format!("{}, 可以使用 --reconcile 从该处重新翻译", message));
+            }
+            println!("{}, 将从该处重新翻译", message);
+            self.chunks.retain(|chunk| chunk.index < index);
+        }
+
+        let expected = self.output_len();
+        let actual = fs::metadata(output_path).map(|m| m.len()).unwrap_or(0);
+        if actual == expected {
+            return Ok(());
+        }
+
+        // 输出文件比记录长说明上次追加后没来得及保存记录, 多出的部分没有提交, 直接截断
+        if actual > expected {
+            println!(
+                "输出文件 {} 末尾有 {} 字节未提交的内容, 已截断",
+                output_path,
+                actual - expected
+            );
+            return truncate_output(output_path, expected);
+        }
+
+        let message = format!("输出文件 {} 长度为 {} 字节, 记录为 {} 字节", output_path, actual, expected);
+        if !reconcile {
+            return Err(format!("{}, 可以使用 --reconcile 回退到一致的位置", message));
+        }
+
+        self.chunks.retain(|chunk| chunk.output_offset + chunk.output_len <= actual);
+        let expected = self.output_len();
+        println!("{}, 回退到第 {} 行", message, self.next_line() + 1);
+        if actual > 0 {
+            truncate_output(output_path, expected)?;
+        }
+        Ok(())
+    }
+
+    fn first_changed_chunk(&self, source_path: &str) -> Result<Option<usize>, String> {
+        first_changed(source_path, self.committed())
+    }
+
+    // 指定的块中原文与记录的哈希不一致的第一块, 用于重试前检查源文件是否改动过
+    pub fn first_changed_among(&self, source_path: &str, indices: &[usize]) -> Result<Option<usize>, String> {
+        first_changed(source_path, self.chunks.iter().filter(|chunk| indices.contains(&chunk.index)))
+    }
+
+    pub fn next_index(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.index + 1).max().unwrap_or(1)
+    }
+
+    // 输出文件中已提交的长度, 之后的内容都视为未完成的写入
+    // 已经写入内容后不能更换输出编码, 否则同一个文件中会混用两种编码
+    pub fn check_output_encoding(&mut self, label: &str) -> Result<(), String> {
+        if self.output_encoding == label {
+            return Ok(());
+        }
+        if self.output_len() > 0 {
+            return Err(format!(
+                "输出文件 {} 使用 {} 编码写入, 与当前设置的 {} 不一致",
+                self.output_path, self.output_encoding, label
+            ));
+        }
+        self.output_encoding = label.to_string();
+        Ok(())
+    }
+
+    pub fn output_len(&self) -> u64 {
+        self.committed().last().map(|chunk| chunk.output_offset + chunk.output_len).unwrap_or(0)
+    }
+
+    fn committed(&self) -> impl Iterator<Item = &ChunkRecord> {
+        self.chunks.iter().filter(|chunk| matches!(chunk.status, ChunkStatus::Done | ChunkStatus::Failed))
+    }
+
+    pub fn chunk_mut(&mut self, index: usize) -> Option<&mut ChunkRecord> {
+        self.chunks.iter_mut().find(|chunk| chunk.index == index)
+    }
+
+    // 上次运行中断时仍在翻译中的记录不会写入输出, 需要重新翻译
+    pub fn discard_in_flight(&mut self) {
+        self.chunks.retain(|chunk| matches!(chunk.status, ChunkStatus::Done | ChunkStatus::Failed));
+    }
+
+    pub fn count(&self, status: ChunkStatus) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.status == status).count()
+    }
+
+    pub fn total_tokens(&self) -> u64 {
+        self.chunks.iter().filter_map(|chunk| chunk.usage.as_ref()).map(|usage| usage.total_tokens).sum()
+    }
+
+    pub fn print_summary(&self) {
+        println!("{} ({}→{}) -> {}", self.source_path, self.source_lang, self.target_lang, self.output_path);
+        println!(
+            "  已完成 {} 块, 失败 {} 块, 翻译中 {} 块, 等待中 {} 块",
+            self.count(ChunkStatus::Done),
+            self.count(ChunkStatus::Failed),
+            self.count(ChunkStatus::InFlight),
+            self.count(ChunkStatus::Pending)
+        );
+        println!("  下一行: {}, 已用tokens: {}", self.next_line() + 1, self.total_tokens());
+
+        let chapters = self.chunks.iter().filter(|chunk| chunk.heading).count();
+        if chapters > 0 {
+            println!("  章节: {}", chapters);
+        }
+
+        let repaired: Vec<String> = self.chunks.iter()
+            .filter_map(|chunk| chunk.repair.map(|repair| format!("{} ({})", chunk.index, repair.label())))
+            .collect();
+        if !repaired.is_empty() {
+            println!("  行数不一致已修复的块: {}", repaired.join(", "));
+        }
+
+        for chunk in self.chunks.iter().filter(|chunk| chunk.status == ChunkStatus::Failed) {
+            println!(
+                "  chunk {} (行 {}) 失败: {}",
+                chunk.index,
+                line_range(chunk.start_line, chunk.end_line),
+                chunk.error.as_deref().unwrap_or("")
+            );
+        }
+    }
+}
+
+fn truncate_output(output_path: &str, len: u64) -> Result<(), String> {
+    let file = fs::OpenOptions::new().write(true).open(output_path)
+        .map_err(|e| format!("无法打开输出文件 {}: {}", output_path, e))?;
+    file.set_len(len)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("无法截断输出文件 {}: {}", output_path, e))
+}
+
+fn hash_range(file: &mut fs::File, start: u64, end: u64) -> io::Result<String> {
+    let mut buffer = vec![0u8; (end - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut buffer)?;
+    Ok(format!("{:x}", Sha256::digest(&buffer)))
+}
+
+fn first_changed<'a>(source_path: &str, chunks: impl Iterator<Item = &'a ChunkRecord>) -> Result<Option<usize>, String> {
+    let mut file = fs::File::open(source_path)
+        .map_err(|e| format!("无法读取源文件 {}: {}", source_path, e))?;
+
+    for chunk in chunks {
+        match hash_range(&mut file, chunk.start_byte, chunk.end_byte) {
+            Ok(hash) if hash == chunk.hash => continue,
+            _ => return Ok(Some(chunk.index)),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 每个测试使用单独的目录, 源文件每行一块, 输出文件每块写入 "T:" 加原文
+    fn setup(name: &str, source: &str) -> (std::path::PathBuf, String, String, JobState) {
+        let dir = std::env::temp_dir().join(format!("dify_translation_state_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source_path = dir.join("source.txt").to_string_lossy().into_owned();
+        let output_path = dir.join("output.txt").to_string_lossy().into_owned();
+        fs::write(&source_path, source).unwrap();
+
+        let mut state = JobState::new(&source_path, "zh", "ja", &output_path);
+        let mut output = String::new();
+        for (i, line) in source.split_inclusive('\n').enumerate() {
+            let translation = format!("T:{}", line);
+            state.chunks.push(ChunkRecord {
+                index: i + 1,
+                start_line: i,
+                end_line: i + 1,
+                start_byte: 0,
+                end_byte: 0,
+                hash: String::new(),
+                status: ChunkStatus::Done,
+                attempts: 1,
+                output_offset: output.len() as u64,
+                output_len: translation.len() as u64,
+                usage: None,
+                error: None,
+                repair: None,
+                heading: false,
+            });
+            output.push_str(&translation);
+        }
+        fs::write(&output_path, &output).unwrap();
+        state.fill_byte_offsets(&source_path).unwrap();
+        (dir, source_path, output_path, state)
+    }
+
+    #[test]
+    fn fills_legacy_byte_offsets_from_lines() {
+        let (dir, _, _, state) = setup("offsets", "一\n二二\n三\n");
+        let ranges: Vec<(u64, u64)> = state.chunks.iter().map(|chunk| (chunk.start_byte, chunk.end_byte)).collect();
+        assert_eq!(ranges, vec![(0, 4), (4, 11), (11, 15)]);
+        assert_eq!(state.chunks[1].hash, format!("{:x}", Sha256::digest("二二\n".as_bytes())));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn truncates_uncommitted_output_tail() {
+        let (dir, source_path, output_path, mut state) = setup("tail", "a\nb\nc\n");
+        let committed = fs::read(&output_path).unwrap();
+        let mut output = committed.clone();
+        output.extend_from_slice(b"T:partial");
+        fs::write(&output_path, output).unwrap();
+
+        state.verify_resume(&source_path, &output_path, false).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), committed);
+        assert_eq!(state.chunks.len(), 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rolls_back_to_first_changed_source_chunk_with_reconcile() {
+        let (dir, source_path, output_path, mut state) = setup("source", "a\nb\nc\n");
+        fs::write(&source_path, "a\nB\nc\n").unwrap();
+
+        let error = state.verify_resume(&source_path, &output_path, false).unwrap_err();
+        assert!(error.contains("chunk 2") && error.contains("--reconcile"));
+        assert_eq!(state.chunks.len(), 3);
+
+        state.verify_resume(&source_path, &output_path, true).unwrap();
+        assert_eq!(state.chunks.len(), 1);
+        assert_eq!((state.next_line(), state.next_byte()), (1, 2));
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "T:a\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rolls_back_to_shorter_output_with_reconcile() {
+        let (dir, source_path, output_path, mut state) = setup("output", "a\nb\nc\n");
+        fs::write(&output_path, "T:a\nT:b").unwrap();
+
+        let error = state.verify_resume(&source_path, &output_path, false).unwrap_err();
+        assert!(error.contains("--reconcile"));
+
+        state.verify_resume(&source_path, &output_path, true).unwrap();
+        assert_eq!(state.chunks.len(), 1);
+        assert_eq!(state.output_len(), 4);
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), "T:a\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
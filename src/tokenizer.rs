@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+// 估算文本的 token 数, 用于按预算切分和试运行时估算费用
+pub trait Tokenizer: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+}
+
+// 默认的估算方式: 中日韩文字每个字约 1 个 token, 其他文字约 4 个字符 1 个 token
+pub struct CjkTokenizer;
+
+impl Tokenizer for CjkTokenizer {
+    fn count(&self, text: &str) -> usize {
+        let mut cjk = 0;
+        let mut other: usize = 0;
+        for c in text.chars() {
+            if is_cjk(c) {
+                cjk += 1;
+            } else if !c.is_whitespace() {
+                other += 1;
+            }
+        }
+        cjk + other.div_ceil(4)
+    }
+}
+
+// 按固定的字符数估算, 适合已经知道模型大致比例的情况
+pub struct CharRatioTokenizer {
+    pub chars_per_token: f64,
+}
+
+impl Tokenizer for CharRatioTokenizer {
+    fn count(&self, text: &str) -> usize {
+        let chars = text.chars().filter(|c| !c.is_whitespace()).count();
+        (chars as f64 / self.chars_per_token).ceil() as usize
+    }
+}
+
+// "cjk" 或 "chars:3.5"
+pub fn tokenizer_from_name(name: &str) -> Result<Arc<dyn Tokenizer>, String> {
+    if name == "cjk" {
+        return Ok(Arc::new(CjkTokenizer));
+    }
+
+    match name.strip_prefix("chars:").and_then(|ratio| ratio.parse::<f64>().ok()) {
+        Some(chars_per_token) if chars_per_token > 0.0 => Ok(Arc::new(CharRatioTokenizer { chars_per_token })),
+        _ => Err(format!("无效的tokenizer: {}, 可以使用 cjk 或 chars:N", name)),
+    }
+}
+
+pub fn default_tokenizer() -> Arc<dyn Tokenizer> {
+    Arc::new(CjkTokenizer)
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3000..=0x303F     // 中日韩标点
+        | 0x3040..=0x30FF   // 平假名、片假名
+        | 0x3400..=0x4DBF   // 扩展A
+        | 0x4E00..=0x9FFF   // 基本汉字
+        | 0xAC00..=0xD7AF   // 韩文
+        | 0xF900..=0xFAFF   // 兼容汉字
+        | 0xFF00..=0xFFEF   // 全角字符
+        | 0x20000..=0x2FFFF // 扩展B及以后
+    )
+}
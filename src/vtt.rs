@@ -0,0 +1,144 @@
+use crate::document::{mask, Document, Unit};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+// 行内标签 (<i>、<c.yellow>、<v Roger>、时间戳) 和字符引用, 翻译时替换为占位符
+static INLINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"</?[a-zA-Z0-9][^>]*>|&(?:[a-zA-Z]+|#[0-9]+|#[xX][0-9a-fA-F]+);").unwrap()
+});
+
+// WebVTT 字幕: 文件头、NOTE/STYLE/REGION 块和字幕块, 块之间用空行分隔
+pub struct Vtt {
+    blocks: Vec<Block>,
+}
+
+enum Block {
+    // 文件头和 NOTE/STYLE/REGION 块原样保留
+    Raw(String),
+    Cue {
+        identifier: Option<String>,
+        timing: String,
+        text: String,
+    },
+}
+
+pub fn parse(source: &str) -> Result<Vtt, String> {
+    let lines: Vec<&str> = source.lines().collect();
+    let first = lines.first().copied().unwrap_or_default();
+    if first != "WEBVTT" && !first.starts_with("WEBVTT ") && !first.starts_with("WEBVTT\t") {
+        return Err("缺少 WEBVTT 文件头".to_string());
+    }
+
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    while start < lines.len() {
+        if lines[start].trim().is_empty() {
+            start += 1;
+            continue;
+        }
+        let end = lines[start..].iter().position(|line| line.trim().is_empty()).map_or(lines.len(), |len| start + len);
+        let block = &lines[start..end];
+
+        let keyword = block[0].split([' ', '\t']).next().unwrap_or_default();
+        if start == 0 || matches!(keyword, "NOTE" | "STYLE" | "REGION") {
+            blocks.push(Block::Raw(block.join("\n")));
+        } else {
+            let (identifier, rest) = if block[0].contains("-->") {
+                (None, block)
+            } else {
+                (Some(block[0].to_string()), &block[1..])
+            };
+            match rest.first() {
+                Some(timing) if timing.contains("-->") => blocks.push(Block::Cue {
+                    identifier,
+                    timing: timing.to_string(),
+                    text: rest[1..].join("\n"),
+                }),
+                _ => return Err(format!("第 {} 行的字幕缺少时间轴", start + 1)),
+            }
+        }
+        start = end;
+    }
+    Ok(Vtt { blocks })
+}
+
+impl Vtt {
+    fn cues(&self) -> impl Iterator<Item = (Option<&String>, &String, &String)> {
+        self.blocks.iter().filter_map(|block| match block {
+            Block::Cue { identifier, timing, text } => Some((identifier.as_ref(), timing, text)),
+            Block::Raw(_) => None,
+        })
+    }
+}
+
+impl Document for Vtt {
+    // 按字幕在文件中的位置编号, 标识符可以省略
+    fn units(&self) -> Vec<Unit> {
+        self.cues()
+            .enumerate()
+            .map(|(i, (_, _, text))| {
+                let (text, tags) = mask(text, &INLINE);
+                Unit { id: (i + 1).to_string(), text, tags }
+            })
+            .collect()
+    }
+
+    fn render(&self, translations: &HashMap<String, String>) -> String {
+        let mut blocks = Vec::new();
+        let mut number = 0;
+        for block in &self.blocks {
+            match block {
+                Block::Raw(raw) => blocks.push(raw.clone()),
+                Block::Cue { identifier, timing, text } => {
+                    number += 1;
+                    let mut lines: Vec<&str> = identifier.iter().map(String::as_str).collect();
+                    lines.push(timing);
+                    // 空行会结束字幕块, 需要去掉
+                    let text = translations.get(&number.to_string()).unwrap_or(text);
+                    lines.extend(text.lines().map(str::trim_end).filter(|line| !line.is_empty()));
+                    blocks.push(lines.join("\n"));
+                }
+            }
+        }
+        format!("{}\n", blocks.join("\n\n"))
+    }
+
+    // 译文中新出现的 & < > 需要转义, 也避免出现 -->
+    fn escape(&self, translation: &str) -> String {
+        translation.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "WEBVTT - 示例\n\nNOTE 这条注释不翻译\n\nSTYLE\n::cue { color: white }\n\nintro\n00:00:01.000 --> 00:00:02.000 align:start\n<v Roger>你好 &amp; <i>再见</i>\n\n00:00:03.000 --> 00:00:04.000\n<00:00:03.500>第二条\n";
+
+    #[test]
+    fn masks_inline_tags_and_escapes_new_markup() {
+        let vtt = parse(SOURCE).unwrap();
+        let units = vtt.units();
+        assert_eq!(units.len(), 2);
+        assert_eq!(units[0].text, "[[0]]你好 [[1]] [[2]]再见[[3]]");
+        assert_eq!(units[0].tags, vec!["<v Roger>", "&amp;", "<i>", "</i>"]);
+        assert_eq!(vtt.escape("a < b & c"), "a &lt; b &amp; c");
+    }
+
+    #[test]
+    fn keeps_cue_identifier_and_settings() {
+        let vtt = parse("WEBVTT\n\nc1\n00:00:01.000 --> 00:00:02.000 position:10%,line-left align:start size:35%\n原文\n").unwrap();
+        let translations = HashMap::from([("1".to_string(), "译文".to_string())]);
+        assert_eq!(
+            vtt.render(&translations),
+            "WEBVTT\n\nc1\n00:00:01.000 --> 00:00:02.000 position:10%,line-left align:start size:35%\n译文\n"
+        );
+    }
+
+    #[test]
+    fn requires_header_and_timing() {
+        assert!(parse("00:00:01.000 --> 00:00:02.000\n字幕\n").is_err());
+        assert!(parse("WEBVTT\n\nid\n字幕\n").is_err());
+    }
+}